@@ -22,12 +22,15 @@ pub enum Value {
     Counter(Inner<u64, DeltaBuf<u64>>),
     Integer(Inner<i64, DeltaBuf<i64>>),
     Float(Inner<f64, VecDeque<f64>>),
+    // bucket boundaries, a counter for each bucket
+    Histogram(Vec<u64>, Vec<Inner<u64, DeltaBuf<u64>>>),
 }
 
 probor_enum_encoder_decoder!(Value {
     #0 Counter(inner #1),
     #1 Integer(inner #1),
     #2 Float(inner #1),
+    #3 Histogram(bounds #1, buckets #2),
 });
 
 
@@ -60,6 +63,11 @@ pub struct DeltaHistory<'a, T:Int> {
     tip: T,
 }
 
+#[derive(Clone)]
+pub struct HistogramHistory<'a> {
+    buckets: Vec<DeltaHistory<'a, u64>>,
+}
+
 #[derive(Clone)]
 pub struct FloatHistory<'a, T:Float+Copy+'static> {
     state: HState,
@@ -74,6 +82,11 @@ impl Value {
             &Value::Counter(ref i) => size_of_val(self) + i.buf.size(),
             &Value::Integer(ref i) => size_of_val(self) + i.buf.size(),
             &Value::Float(ref i) => size_of_val(self) + i.buf.size(),
+            &Value::Histogram(ref bounds, ref b) => {
+                size_of_val(self) + bounds.len()*size_of::<u64>() +
+                b.iter().map(|i| size_of_val(i) + i.buf.size())
+                    .fold(0, |a, b| a + b)
+            }
         }
     }
     pub fn new(value: &TipValue, age: u64) -> Value {
@@ -95,6 +108,13 @@ impl Value {
                 age: age,
                 buf: VecDeque::new(),
             }),
+            &T::Histogram(ref bounds, ref counts) => V::Histogram(
+                bounds.clone(),
+                counts.iter().map(|&v| Inner {
+                    tip: v,
+                    age: age,
+                    buf: DeltaBuf::new(),
+                }).collect()),
             &T::State(_) => unreachable!(),
         }
     }
@@ -115,6 +135,17 @@ impl Value {
                 b.push(v, age);
                 return true;
             }
+            (&mut V::Histogram(ref bounds, ref mut b),
+             &T::Histogram(ref nbounds, ref counts)) => {
+                if bounds != nbounds || b.len() != counts.len() {
+                    // Buckets changed, so the history is useless
+                    return false;
+                }
+                for (bucket, &v) in b.iter_mut().zip(counts.iter()) {
+                    bucket.push(v, age);
+                }
+                return true;
+            }
             _ => {}
         }
         return false;
@@ -125,6 +156,14 @@ impl Value {
             &mut V::Counter(ref mut b) => b.truncate(age),
             &mut V::Integer(ref mut b) => b.truncate(age),
             &mut V::Float(ref mut b) => b.truncate(age),
+            &mut V::Histogram(_, ref mut b) => {
+                let mut result = false;
+                for bucket in b.iter_mut() {
+                    let keep = bucket.truncate(age);
+                    result = result || keep;
+                }
+                result
+            }
         }
     }
     pub fn age(&self) -> u64 {
@@ -133,6 +172,7 @@ impl Value {
             &Counter(ref b) => b.age(),
             &Integer(ref b) => b.age(),
             &Float(ref b) => b.age(),
+            &Histogram(_, ref b) => b.get(0).map(|x| x.age()).unwrap_or(0),
         }
     }
     /// Returns same value as tip_value is value is newer than min_age
@@ -146,6 +186,8 @@ impl Value {
             => Some(D::Integer(b.tip())),
             &S::Float(ref b) if b.age() >= min_age
             => Some(D::Float(b.tip())),
+            &S::Histogram(..) if self.age() >= min_age
+            => Some(self.tip_value()),
             _ => None,
         }
     }
@@ -156,6 +198,8 @@ impl Value {
             &S::Counter(ref b) => D::Counter(b.tip()),
             &S::Integer(ref b) => D::Integer(b.tip()),
             &S::Float(ref b) => D::Float(b.tip()),
+            &S::Histogram(ref bounds, ref b) => D::Histogram(bounds.clone(),
+                b.iter().map(|x| x.tip()).collect()),
         }
     }
 }
@@ -234,6 +278,36 @@ impl<'a, T:Int> Iterator for DeltaHistory<'a, T> {
     }
 }
 
+impl<'a> HistogramHistory<'a> {
+    pub fn new(buckets: &'a [Inner<u64, DeltaBuf<u64>>], current_age: u64)
+        -> HistogramHistory<'a>
+    {
+        HistogramHistory {
+            buckets: buckets.iter().map(|b| b.history(current_age)).collect(),
+        }
+    }
+}
+
+impl<'a> Iterator for HistogramHistory<'a> {
+    type Item = Option<Vec<u64>>;
+    fn next(&mut self) -> Option<Option<Vec<u64>>> {
+        let mut result = Vec::with_capacity(self.buckets.len());
+        let mut valid = true;
+        for bucket in self.buckets.iter_mut() {
+            match bucket.next() {
+                Some(Some(x)) => result.push(x),
+                Some(None) => valid = false,
+                None => return None,
+            }
+        }
+        if valid {
+            Some(Some(result))
+        } else {
+            Some(None)
+        }
+    }
+}
+
 impl<'a, T:Float> Iterator for FloatHistory<'a, T> {
     type Item = Option<T>;
     fn next(&mut self) -> Option<Option<T>> {
@@ -499,8 +573,8 @@ mod serde {
 mod test {
     use std::io::Cursor;
     use {Backlog, Key};
-    use super::{Value, Inner};
-    use values::Value::Counter;
+    use super::{Value, Inner, HistogramHistory};
+    use values::Value::{Counter, Histogram};
     use std::collections::{HashMap, HashSet};
     use probor::{Encodable, Decodable, Encoder, Decoder, Config, decode};
 
@@ -520,6 +594,37 @@ mod test {
         assert_eq!(backlog.values.len(), 3);
     }
 
+    #[test]
+    fn test_histogram() {
+        let mut backlog = Backlog::new();
+        let key = Key::metric("latency");
+        backlog.push((1000, 10), vec![
+            (&key, &Histogram(vec![1, 10], vec![1, 2, 3])),
+        ].into_iter());
+        backlog.push((2000, 10), vec![
+            (&key, &Histogram(vec![1, 10], vec![2, 2, 5])),
+        ].into_iter());
+        let value = &backlog.values[&key];
+        if let Value::Histogram(_, ref buckets) = *value {
+            let hist = HistogramHistory::new(buckets, backlog.age)
+                .collect::<Vec<_>>();
+            assert_eq!(hist, vec![Some(vec![2, 2, 5]), Some(vec![1, 2, 3])]);
+        } else {
+            panic!("Histogram expected");
+        }
+        // Changed buckets replace the history
+        backlog.push((3000, 10), vec![
+            (&key, &Histogram(vec![5], vec![7, 0])),
+        ].into_iter());
+        match backlog.values[&key].tip_value() {
+            Histogram(b, c) => {
+                assert_eq!(b, vec![5]);
+                assert_eq!(c, vec![7, 0]);
+            }
+            _ => panic!("Histogram expected"),
+        }
+    }
+
     fn roundtrip<T:Encodable+Decodable>(v: &T) -> T {
         let mut e = Encoder::new(Vec::new());
         v.encode(&mut e).unwrap();
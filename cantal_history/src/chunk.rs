@@ -8,6 +8,7 @@ pub enum HistoryChunk {
     Counter(Vec<Option<u64>>),
     Integer(Vec<Option<i64>>),
     Float(Vec<Option<f64>>),
+    Histogram(Vec<u64>, Vec<Option<Vec<u64>>>),
 }
 
 probor_enum_encoder_decoder!(HistoryChunk {
@@ -15,6 +16,7 @@ probor_enum_encoder_decoder!(HistoryChunk {
     #1 Counter(items #1),
     #2 Integer(items #1),
     #3 Float(items #1),
+    #4 Histogram(bounds #1, items #2),
 });
 
 pub struct HistoryChunkIter<'a> {
@@ -31,6 +33,7 @@ impl HistoryChunk {
             &Counter(ref slc) => slc.len(),
             &Integer(ref slc) => slc.len(),
             &Float(ref slc) => slc.len(),
+            &Histogram(_, ref slc) => slc.len(),
         };
         assert!(size >= 1);
         HistoryChunkIter {
@@ -56,6 +59,8 @@ impl<'a> Iterator for HistoryChunkIter<'a> {
             &S::Counter(ref slc) => slc[idx].map(D::Counter),
             &S::Integer(ref slc) => slc[idx].map(D::Integer),
             &S::Float(ref slc) => slc[idx].map(D::Float),
+            &S::Histogram(ref bounds, ref slc) => slc[idx].as_ref()
+                .map(|x| D::Histogram(bounds.clone(), x.clone())),
         })
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -76,6 +81,8 @@ impl<'a> DoubleEndedIterator for HistoryChunkIter<'a> {
             &S::Counter(ref slc) => slc[self.end_index].map(D::Counter),
             &S::Integer(ref slc) => slc[self.end_index].map(D::Integer),
             &S::Float(ref slc) => slc[self.end_index].map(D::Float),
+            &S::Histogram(ref bounds, ref slc) => slc[self.end_index]
+                .as_ref().map(|x| D::Histogram(bounds.clone(), x.clone())),
         })
     }
 }
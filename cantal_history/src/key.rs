@@ -1,24 +1,39 @@
+use std::fmt;
 use std::mem::size_of_val;
 use std::io::Cursor;
 use std::collections::BTreeMap;
 use std::marker::PhantomData;
 use std::iter::Peekable;
+use std::str::from_utf8;
 
-use cbor::{Encoder, Decoder, Config};
+use cbor::{Encoder, Decoder, Config, DecodeResult};
+use cbor::types::Type;
 use serialize::json::Json;
 
 use Key;
 
+
+/// A value of a single pair in the key
+///
+/// Only strings were supported as values before version 3 of the
+/// persistent format, so text is what most of the code expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum KeyValue<'a> {
+    Text(&'a str),
+    Int(i64),
+    Bool(bool),
+}
+
 struct Merge<'a, A, B>(usize, Peekable<A>, Peekable<B>, PhantomData<&'a A>)
-    where A: Iterator<Item=(&'a str, &'a str)> + 'a,
-          B: Iterator<Item=(&'a str, &'a str)> + 'a;
+    where A: Iterator<Item=(&'a str, KeyValue<'a>)> + 'a,
+          B: Iterator<Item=(&'a str, KeyValue<'a>)> + 'a;
 
 impl<'a, A, B> Iterator for Merge<'a, A, B>
-    where A: Iterator<Item=(&'a str, &'a str)>,
-          B: Iterator<Item=(&'a str, &'a str)>
+    where A: Iterator<Item=(&'a str, KeyValue<'a>)>,
+          B: Iterator<Item=(&'a str, KeyValue<'a>)>
 {
-    type Item = (&'a str, &'a str);
-    fn next(&mut self) -> Option<(&'a str, &'a str)> {
+    type Item = (&'a str, KeyValue<'a>);
+    fn next(&mut self) -> Option<(&'a str, KeyValue<'a>)> {
         match (self.1.peek(), self.2.peek()) {
             (_, None) => self.1.next(),
             (None, _) => self.2.next(),
@@ -34,14 +49,65 @@ impl<'a, A, B> Iterator for Merge<'a, A, B>
 }
 
 impl<'a, A, B> ExactSizeIterator for Merge<'a, A, B>
-    where A: Iterator<Item=(&'a str, &'a str)>,
-          B: Iterator<Item=(&'a str, &'a str)>
+    where A: Iterator<Item=(&'a str, KeyValue<'a>)>,
+          B: Iterator<Item=(&'a str, KeyValue<'a>)>
 {
     fn len(&self) -> usize {
         self.0
     }
 }
 
+impl<'a> KeyValue<'a> {
+    fn encode(&self, e: &mut Encoder<Vec<u8>>) {
+        match *self {
+            KeyValue::Text(x) => e.text(x).unwrap(),
+            KeyValue::Int(x) => e.i64(x).unwrap(),
+            KeyValue::Bool(x) => e.bool(x).unwrap(),
+        }
+    }
+    fn from_json(json: &'a Json) -> Result<KeyValue<'a>, ()> {
+        match json {
+            &Json::String(ref val) => Ok(KeyValue::Text(&val[..])),
+            &Json::I64(val) => Ok(KeyValue::Int(val)),
+            &Json::U64(val) if val <= i64::max_value() as u64 => {
+                Ok(KeyValue::Int(val as i64))
+            }
+            &Json::Boolean(val) => Ok(KeyValue::Bool(val)),
+            // floats are not exact enough to be used as a key,
+            // and nested values aren't supported at all
+            _ => Err(()),
+        }
+    }
+}
+
+impl<'a> fmt::Display for KeyValue<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyValue::Text(x) => f.write_str(x),
+            KeyValue::Int(x) => write!(f, "{}", x),
+            KeyValue::Bool(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+/// Reads a single value of any type supported in the key
+pub fn read_value<'x, 'b>(d: &'x mut Decoder<Cursor<&'b [u8]>>)
+    -> DecodeResult<KeyValue<'x>>
+{
+    let k = d.kernel();
+    let ti = try!(k.typeinfo());
+    match ti {
+        (Type::Text, i) if i != 31 => {
+            let max = Config::default().max_len_text;
+            let data = try!(k.raw_slice(i, max));
+            from_utf8(data).map(KeyValue::Text).map_err(From::from)
+        }
+        (Type::Bool, _) => k.bool(&ti).map(KeyValue::Bool),
+        // this also returns an error for all other types
+        _ => k.i64(&ti).map(KeyValue::Int),
+    }
+}
+
 
 impl Key {
     /// Size of key in bytes, for debugging
@@ -50,7 +116,7 @@ impl Key {
     }
     /// Note: caller must ensure that order is Okay
     fn from_iter<'x, I>(pairs: I) -> Key
-        where I :Iterator<Item=(&'x str, &'x str)>+ExactSizeIterator
+        where I :Iterator<Item=(&'x str, KeyValue<'x>)>+ExactSizeIterator
     {
         if pairs.len() == 0 {
             return Key(None);
@@ -59,13 +125,14 @@ impl Key {
         e.object(pairs.len()).unwrap();
         for (k, v) in pairs {
             e.text(&k).unwrap();
-            // TODO(tailhook) optimize numbers
-            e.text(&v).unwrap();
+            v.encode(&mut e);
         }
         Key(Some(e.into_writer().into_boxed_slice()))
     }
     /// Creates a key json object with additional pairs added
     ///
+    /// Values of the json object may be strings, integers or booleans.
+    ///
     /// Note pairs should be sorted
     pub fn from_json(json: &Json, pairs: &[(&str, &str)]) -> Result<Key, ()> {
         debug_assert!(pairs.iter().zip(pairs.iter().skip(1))
@@ -77,16 +144,13 @@ impl Key {
             let num = btree.len() + pairs.len() -
                 pairs.iter().filter(|&&(x, _)| btree.contains_key(x)).count();
             let res = Key::from_iter(Merge(num,
-                pairs.iter().cloned().peekable(),
+                pairs.iter().map(|&(k, v)| (k, KeyValue::Text(v))).peekable(),
                 btree.iter().map(|(ref k, v)| {
-                    match v {
-                        &Json::String(ref val) => {
-                            (&k[..], &val[..])
-                        }
-                        // TODO(tailhook) probably support numbers
-                        _ => {
+                    match KeyValue::from_json(v) {
+                        Ok(val) => (&k[..], val),
+                        Err(()) => {
                             errors += 1;
-                            (&k[..], "")
+                            (&k[..], KeyValue::Text(""))
                         }
                     }
                 }).peekable(),
@@ -104,31 +168,51 @@ impl Key {
     /// order. This method is inteded to be used with literal values put in
     /// the code, so it should be easy to put them in the right order
     pub fn pairs(pairs: &[(&str, &str)]) -> Key {
+        debug_assert!(pairs.iter().zip(pairs.iter().skip(1))
+            .all(|(&(a, _), &(b, _))| a < b));
+        Key::from_iter(pairs.iter().map(|&(k, v)| (k, KeyValue::Text(v))))
+    }
+    /// Same as `pairs` but allows values of any supported type
+    pub fn values(pairs: &[(&str, KeyValue)]) -> Key {
         debug_assert!(pairs.iter().zip(pairs.iter().skip(1))
             .all(|(&(a, _), &(b, _))| a < b));
         Key::from_iter(pairs.iter().cloned())
     }
     pub fn from_pair(key: &str, val: &str) -> Key {
-        Key::from_iter([(key, val)].iter().cloned())
+        Key::from_iter([(key, KeyValue::Text(val))].iter().cloned())
     }
     pub fn metric(metric: &str) -> Key {
-        Key::from_iter([("metric", metric)].iter().cloned())
+        Key::from_iter([("metric", KeyValue::Text(metric))].iter().cloned())
     }
 
     pub fn as_bytes<'x>(&'x self) -> &'x [u8] {
         self.0.as_ref().map(|x| &x[..]).unwrap_or(b"")
     }
 
+    /// Calls `f` with the textual representation of the value
+    ///
+    /// Integers and booleans are formatted the same way as in JSON, so
+    /// code that only cares about strings works for all keys.
     pub fn get_with<'x, F, T>(&'x self, name: &str, f: F) -> Option<T>
         where F: FnOnce(&str) -> T
+    {
+        self.get_value_with(name, |value| match value {
+            KeyValue::Text(x) => f(x),
+            KeyValue::Int(x) => f(&x.to_string()),
+            KeyValue::Bool(true) => f("true"),
+            KeyValue::Bool(false) => f("false"),
+        })
+    }
+
+    pub fn get_value_with<'x, F, T>(&'x self, name: &str, f: F) -> Option<T>
+        where F: FnOnce(KeyValue) -> T
     {
         self.0.as_ref().and_then(|b| {
             let mut d = Decoder::new(Config::default(), Cursor::new(&b[..]));
             let num = d.object().unwrap();
             for _ in 0..num {
                 if d.text_borrow().unwrap() == name {
-                    // TODO(tailhook) other types may work in future
-                    return Some(f(d.text_borrow().unwrap()));
+                    return Some(f(read_value(&mut d).unwrap()));
                 } else {
                     d.skip().unwrap();
                 }
@@ -137,6 +221,15 @@ impl Key {
         })
     }
 
+    /// Returns a key that consists of a single pair `name` from this key
+    ///
+    /// The type of the value is preserved.
+    pub fn pair(&self, name: &str) -> Option<Key> {
+        self.get_value_with(name, |value| {
+            Key::from_iter([(name, value)].iter().cloned())
+        })
+    }
+
     pub fn empty() -> Key {
         Key(None)
     }
@@ -145,17 +238,19 @@ impl Key {
 
 mod serde {
     use std::io::Cursor;
+    use cbor;
     use probor::{Decodable, Decoder, DecodeError, Input, Config};
     use probor::{Encodable, Encoder, EncodeError, Output};
+    use super::read_value;
     use Key;
 
     fn validate_key(val: &[u8]) -> Result<(), &'static str> {
-        let mut d = Decoder::new(Config::default(), Cursor::new(val));
+        let mut d = cbor::Decoder::new(cbor::Config::default(),
+                                       Cursor::new(val));
         let num = try!(d.object().map_err(|_| "Invalid key"));
         for _ in 0..num {
-            // TODO(tailhook) other types may work in future
-            try!(d.text_borrow().map_err(|_| "Invalid key"));
             try!(d.text_borrow().map_err(|_| "Invalid key"));
+            try!(read_value(&mut d).map_err(|_| "Invalid key"));
         }
         if d.into_reader().position() as usize != val.len() {
             return Err("Invalid key: extra data");
@@ -192,6 +287,7 @@ mod std_trait {
     use std::fmt::{Debug, Formatter, Error};
     use std::io::Cursor;
     use cbor::{Decoder, Config};
+    use super::read_value;
     use Key;
 
     impl Debug for Key {
@@ -209,11 +305,10 @@ mod std_trait {
                 if idx > 0 {
                     try!(write!(f, ", "));
                 }
-                // TODO(tailhook) other types may work in future
                 try!(write!(f, "{}: ",
                     try!(d.text_borrow().map_err(|_| Error))));
                 try!(write!(f, "{}",
-                    try!(d.text_borrow().map_err(|_| Error))));
+                    try!(read_value(&mut d).map_err(|_| Error))));
             }
             try!(write!(f, "}}"));
             Ok(())
@@ -232,6 +327,7 @@ mod std_trait {
 mod test {
     use serialize::json;
     use Key;
+    use super::KeyValue;
 
     fn from_json() {
         let key = Key::from_json(&json::Json::from_str(
@@ -242,4 +338,24 @@ mod test {
         assert_eq!(&key.0.unwrap()[..],
             &b"\xa3fmetricdtestcpidd1234czooebasic"[..]);
     }
+
+    #[test]
+    fn from_json_values() {
+        let key = Key::from_json(&json::Json::from_str(
+                r#"{"metric": "test", "shard": 3, "leader": true}"#
+                ).unwrap(),
+                &[]
+            ).unwrap();
+        assert_eq!(key.get_value_with("shard", |x| x == KeyValue::Int(3)),
+                   Some(true));
+        assert_eq!(key.get_with("shard", |x| x.to_string()),
+                   Some(String::from("3")));
+        assert_eq!(key.get_with("leader", |x| x == "true"), Some(true));
+        assert_eq!(format!("{:?}", key),
+                   "Key {leader: true, metric: test, shard: 3}");
+        assert_eq!(key.pair("shard"),
+                   Some(Key::values(&[("shard", KeyValue::Int(3))])));
+        assert!(Key::from_json(&json::Json::from_str(
+            r#"{"metric": "test", "ratio": 0.5}"#).unwrap(), &[]).is_err());
+    }
 }
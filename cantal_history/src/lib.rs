@@ -17,12 +17,13 @@ mod merge;
 mod serde;
 mod tstamp;
 
-pub use backlog::{Backlog, Value};
+pub use backlog::{Backlog, Value, HistogramHistory};
 pub use tip::Tip;
 pub use merge::{ChunkSet, ValueSet};
 pub use chunk::HistoryChunk as Chunk;
 pub use serde::VersionInfo;
 pub use tstamp::compare_timestamps;
+pub use key::KeyValue;
 use serialize::json::Json;
 
 pub type TimeStamp = u64;  // Milliseconds
@@ -9,6 +9,7 @@ pub enum ChunkSet<'a> {
     Counters(Vec<&'a Vec<Option<u64>>>),
     Integers(Vec<&'a Vec<Option<i64>>>),
     Floats(Vec<&'a Vec<Option<f64>>>),
+    Histograms(Vec<(&'a Vec<u64>, &'a Vec<Option<Vec<u64>>>)>),
     Conflict,
 }
 
@@ -19,6 +20,7 @@ pub enum ValueSet<'a> {
     Counters(Vec<u64>),
     Integers(Vec<i64>),
     Floats(Vec<f64>),
+    Histograms(Vec<(&'a Vec<u64>, &'a Vec<u64>)>),
     Conflict,
 }
 
@@ -33,6 +35,8 @@ impl<'a> ChunkSet<'a> {
                 &C::Counter(ref item) => S::Counters(vec![item]),
                 &C::Integer(ref item) => S::Integers(vec![item]),
                 &C::Float(ref item) => S::Floats(vec![item]),
+                &C::Histogram(ref b, ref item)
+                => S::Histograms(vec![(b, item)]),
             },
             S::Conflict => S::Conflict,
             _ => {
@@ -49,6 +53,10 @@ impl<'a> ChunkSet<'a> {
                     (&mut S::Floats(ref mut x), &C::Float(ref item)) => {
                         x.push(item);
                     }
+                    (&mut S::Histograms(ref mut x),
+                     &C::Histogram(ref b, ref item)) => {
+                        x.push((b, item));
+                    }
                     _ => return S::Conflict,
                 }
                 self
@@ -70,6 +78,8 @@ impl<'a> ValueSet<'a> {
                 &V::Counter(item) => S::Counters(vec![item]),
                 &V::Integer(item) => S::Integers(vec![item]),
                 &V::Float(item) => S::Floats(vec![item]),
+                &V::Histogram(ref b, ref item)
+                => S::Histograms(vec![(b, item)]),
             },
             S::Conflict => S::Conflict,
             _ => {
@@ -86,6 +96,10 @@ impl<'a> ValueSet<'a> {
                     (&mut S::Floats(ref mut x), &V::Float(item)) => {
                         x.push(item);
                     }
+                    (&mut S::Histograms(ref mut x),
+                     &V::Histogram(ref b, ref item)) => {
+                        x.push((b, item));
+                    }
                     _ => return S::Conflict,
                 }
                 self
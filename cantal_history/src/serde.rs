@@ -6,7 +6,14 @@ pub struct VersionInfo {
 
 impl VersionInfo {
     pub fn current() -> VersionInfo {
-        VersionInfo { version: 2 }
+        VersionInfo { version: 3 }
+    }
+    /// Returns true if history of this version can be read
+    ///
+    /// Version 3 allows integer and boolean values in keys, which is a
+    /// superset of version 2, so the latter is read without conversion.
+    pub fn is_supported(&self) -> bool {
+        self.version == 2 || self.version == 3
     }
 }
 
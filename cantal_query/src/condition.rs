@@ -5,6 +5,10 @@ use history::Key;
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct RegexWrap(Regex);
 
+/// A condition on the key of the metric
+///
+/// Integer and boolean values of the key are compared by their textual
+/// (JSON) representation, so `["Eq", "shard", "3"]` matches `{"shard": 3}`
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Condition {
     Eq(String, String),
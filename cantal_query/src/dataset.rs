@@ -13,6 +13,7 @@ pub enum Conflict {
     CantSumTimestamps,
     CantSumStates,
     CantDerive,
+    NotAHistogram,
 }
 
 probor_enum_encoder_decoder!(Conflict {
@@ -21,6 +22,7 @@ probor_enum_encoder_decoder!(Conflict {
     #102 CantSumTimestamps(),
     #103 CantSumStates(),
     #104 CantDerive(),
+    #105 NotAHistogram(),
 });
 
 #[derive(Debug)]
@@ -52,5 +52,7 @@ fn derive_series(chunk: Chunk, timestamps: Vec<TimeStamp>)
         Counter(items) => derive_vec(items, timestamps),
         Integer(items) => derive_vec(items, timestamps),
         Float(items) => derive_vec(items, timestamps),
+        // Use Percentile to get something useful out of histogram
+        Histogram(b, x) => (Histogram(b, x), timestamps),
     }
 }
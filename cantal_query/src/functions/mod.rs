@@ -1,5 +1,6 @@
 mod sum;
 mod derive;
+mod percentile;
 
 use {Function, Dataset, UndefFilter};
 
@@ -15,6 +16,7 @@ impl Function {
             &SumBy(ref key, UndefFilter::Ignore, total)
             =>  sum::sum_by(&key, total, d),
             &StateChart(_num) => unimplemented!(),
+            &Percentile(pct) => percentile::percentile(pct, d),
         }
     }
 }
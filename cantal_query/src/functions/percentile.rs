@@ -0,0 +1,124 @@
+use history::{Key, Chunk, TimeStamp};
+use values::Value;
+use {Dataset, Conflict, TimeSlice};
+
+
+pub fn percentile(pct: u8, src: Dataset) -> Dataset {
+    use Dataset::*;
+    match src {
+        MultiSeries(vec) => {
+            let mut result = Vec::with_capacity(vec.len());
+            for (key, chunk, ts) in vec.into_iter() {
+                match series_percentile(pct, chunk, ts) {
+                    Ok((chunk, ts)) => result.push((key, chunk, ts)),
+                    Err(c) => return Incompatible(c),
+                }
+            }
+            MultiSeries(result)
+        }
+        SingleSeries(key, chunk, ts) => {
+            match series_percentile(pct, chunk, ts) {
+                Ok((chunk, ts)) => SingleSeries(key, chunk, ts),
+                Err(c) => Incompatible(c),
+            }
+        }
+        MultiTip(vec) => {
+            let mut result = Vec::with_capacity(vec.len());
+            for (key, value, tslice) in vec.into_iter() {
+                match tip_percentile(pct, key, value, tslice) {
+                    Ok(Some(item)) => result.push(item),
+                    Ok(None) => {}
+                    Err(c) => return Incompatible(c),
+                }
+            }
+            MultiTip(result)
+        }
+        SingleTip(key, value, tslice) => {
+            match tip_percentile(pct, key, value, tslice) {
+                Ok(Some((k, v, tslice))) => SingleTip(k, v, tslice),
+                Ok(None) => Empty,
+                Err(c) => Incompatible(c),
+            }
+        }
+        Chart(_) => Incompatible(Conflict::NotAHistogram),
+        Incompatible(x) => Incompatible(x),
+        Empty => Empty,
+    }
+}
+
+/// Estimates the percentile using linear interpolation inside the bucket
+///
+/// Returns None if there are no values in the histogram. Values in the last
+/// bucket (larger than all boundaries) are estimated as the last boundary.
+pub fn estimate(pct: u8, bounds: &[u64], counts: &[u64]) -> Option<f64> {
+    let total = counts.iter().fold(0, |a, &b| a + b);
+    if total == 0 {
+        return None;
+    }
+    let rank = total as f64 * pct as f64 / 100.;
+    let mut seen = 0u64;
+    for (idx, &count) in counts.iter().enumerate() {
+        if count > 0 && (seen + count) as f64 >= rank {
+            if idx >= bounds.len() {
+                return bounds.last().map(|&x| x as f64).or(Some(0.));
+            }
+            let lower = if idx == 0 { 0. } else { bounds[idx-1] as f64 };
+            let upper = bounds[idx] as f64;
+            let part = (rank - seen as f64) / count as f64;
+            return Some(lower + (upper - lower) * part);
+        }
+        seen += count;
+    }
+    return bounds.last().map(|&x| x as f64).or(Some(0.));
+}
+
+fn tip_percentile(pct: u8, key: Key, value: Value, tslice: TimeSlice)
+    -> Result<Option<(Key, Value, TimeSlice)>, Conflict>
+{
+    match value {
+        Value::Histogram(ref bounds, ref counts) => {
+            Ok(estimate(pct, bounds, counts)
+               .map(|x| (key, Value::Float(x), tslice)))
+        }
+        _ => Err(Conflict::NotAHistogram),
+    }
+}
+
+/// Histogram series contain ever-growing counters, so we compute percentile
+/// of the values accounted between each two consecutive points
+fn series_percentile(pct: u8, chunk: Chunk, timestamps: Vec<TimeStamp>)
+    -> Result<(Chunk, Vec<TimeStamp>), Conflict>
+{
+    let (bounds, items) = match chunk {
+        Chunk::Histogram(bounds, items) => (bounds, items),
+        _ => return Err(Conflict::NotAHistogram),
+    };
+    let first = items.iter().zip(&timestamps);
+    let second = items.iter().skip(1);
+    let (nval, ts) = first.zip(second).map(|((a, &ta), b)| {
+        match (a, b) {
+            (&Some(ref a), &Some(ref b))
+            if a.iter().zip(b).all(|(x, y)| x >= y) => {
+                let diff = a.iter().zip(b).map(|(x, y)| x - y)
+                    .collect::<Vec<_>>();
+                (estimate(pct, &bounds, &diff), ta)
+            }
+            _ => (None, ta),
+        }
+    }).unzip();
+    Ok((Chunk::Float(nval), ts))
+}
+
+#[cfg(test)]
+mod test {
+    use super::estimate;
+
+    #[test]
+    fn interpolation() {
+        assert_eq!(estimate(50, &[10, 20], &[0, 0, 0]), None);
+        assert_eq!(estimate(50, &[10, 20], &[0, 10, 0]), Some(15.));
+        assert_eq!(estimate(100, &[10, 20], &[5, 5, 0]), Some(20.));
+        assert_eq!(estimate(99, &[10, 20], &[5, 5, 10]), Some(20.));
+        assert_eq!(estimate(0, &[10, 20], &[0, 4, 0]), Some(10.));
+    }
+}
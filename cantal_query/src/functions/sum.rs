@@ -60,8 +60,9 @@ fn sum_series_by(by: &str, vec: Vec<(Key, Chunk, Vec<TimeStamp>)>)
 {
     let mut map = HashMap::new();
     for (key, total, src) in vec.into_iter() {
-        key.get_with(by, |x| x.to_string()).map(|kstr| {
-            map.entry(kstr)
+        // grouping by a single-pair key keeps original type of the value
+        key.pair(by).map(|group| {
+            map.entry(group)
                 .or_insert_with(Vec::new)
                 .push((key, total, src));
         });
@@ -73,7 +74,7 @@ fn sum_series_by(by: &str, vec: Vec<(Key, Chunk, Vec<TimeStamp>)>)
         } else {
             vec.pop().unwrap()
         };
-        res.push((Key::from_pair(by, &key[..]), datapoints, ts));
+        res.push((key, datapoints, ts));
     }
     return Ok(res);
 }
@@ -104,6 +105,14 @@ fn sum_series(src: &Vec<(Key, Chunk, Vec<TimeStamp>)>)
             .fold(vec![None; data_points], vec_sum)),
         S::Floats(lst) => C::Float(lst.iter()
             .fold(vec![None; data_points], vec_sum)),
+        S::Histograms(lst) => {
+            let bounds = lst[0].0;
+            if lst.iter().any(|&(b, _)| b != bounds) {
+                return Err(Conflict::Dissimilar);
+            }
+            C::Histogram(bounds.clone(), lst.iter()
+                .fold(vec![None; data_points], hist_vec_sum))
+        }
         S::States(_) => return Err(Conflict::CantSumStates),
         S::Conflict => return Err(Conflict::Dissimilar),
     };
@@ -124,6 +133,24 @@ fn vec_sum<X:Add<X, Output=X>+Copy>(mut target: Vec<Option<X>>,
     return target;
 }
 
+fn hist_vec_sum(mut target: Vec<Option<Vec<u64>>>,
+                source: &(&Vec<u64>, &Vec<Option<Vec<u64>>>))
+    -> Vec<Option<Vec<u64>>>
+{
+    for i in 0..target.len() {
+        match (&mut target[i], &source.1[i]) {
+            (&mut Some(ref mut x), &Some(ref y)) => {
+                for (a, b) in x.iter_mut().zip(y.iter()) {
+                    *a += *b;
+                }
+            }
+            (x @ &mut None, y) => *x = y.clone(),
+            (&mut Some(_), &None) => {}
+        }
+    }
+    return target;
+}
+
 fn sum_iter<A:Add<A,Output=A>, I:Iterator<Item=A>>(mut iter: I) -> A {
     let mut x = iter.next().unwrap();
     for y in iter {
@@ -157,6 +184,19 @@ fn sum_tip(mut src: Vec<(Key, Value, TimeSlice)>) -> Dataset {
         S::Counters(lst) => V::Counter(sum_iter(lst.into_iter())),
         S::Integers(lst) => V::Integer(sum_iter(lst.into_iter())),
         S::Floats(lst) => V::Float(sum_iter(lst.into_iter())),
+        S::Histograms(lst) => {
+            let bounds = lst[0].0;
+            if lst.iter().any(|&(b, _)| b != bounds) {
+                return Dataset::Incompatible(Conflict::Dissimilar);
+            }
+            let mut counts = vec![0; lst[0].1.len()];
+            for &(_, item) in &lst {
+                for (a, b) in counts.iter_mut().zip(item.iter()) {
+                    *a += *b;
+                }
+            }
+            V::Histogram(bounds.clone(), counts)
+        }
         S::States(_) => return Dataset::Incompatible(Conflict::CantSumStates),
         S::Conflict => return Dataset::Incompatible(Conflict::Dissimilar),
     };
@@ -1,4 +1,4 @@
-use history::{History, Value, Chunk, Backlog, TimeStamp};
+use history::{History, Value, Chunk, Backlog, TimeStamp, HistogramHistory};
 use values::Value as TipValue;
 
 use {Rule, Source, Dataset, Extract, Function, TimeSlice};
@@ -78,6 +78,10 @@ pub fn extract_single(value: &Value, bl: &Backlog, extract: &Extract)
                     let ts = bl.timestamps[(bl.age - x.age()) as usize].0;
                     (V::Float(x.tip()), (ts, ts))
                 }
+                &B::Histogram(..) => {
+                    let ts = bl.timestamps[(bl.age - value.age()) as usize].0;
+                    (value.tip_value(), (ts, ts))
+                }
             }
         }),
         &DiffToAtMost(n) => {
@@ -112,6 +116,19 @@ pub fn extract_single(value: &Value, bl: &Backlog, extract: &Extract)
                             (bl.timestamps[cur].0, bl.timestamps[idx].0))
                     })
                 }
+                &B::Histogram(ref bounds, ref buckets) => {
+                    HistogramHistory::new(buckets, bl.age)
+                     .enumerate().skip(1).take(n)
+                     .filter_map(|(idx, x)| x.map(|y| (idx, y))).last()
+                     .map(|(idx, x)| {
+                        let cur = (bl.age - value.age()) as usize;
+                        assert!(idx >= cur);
+                        (V::Histogram(bounds.clone(), buckets.iter().zip(x)
+                            .map(|(b, old)| b.tip().saturating_sub(old))
+                            .collect()),
+                            (bl.timestamps[cur].0, bl.timestamps[idx].0))
+                    })
+                }
             }
         },
         &HistoryByNum(_) => None,
@@ -139,6 +156,9 @@ pub fn extract_multi(value: &Value, bl: &Backlog, extract: &Extract)
                 => C::Integer(x.history(bl.age).take(n).collect()),
                 &B::Float(ref x)
                 => C::Float(x.history(bl.age).take(n).collect()),
+                &B::Histogram(ref bounds, ref x)
+                => C::Histogram(bounds.clone(),
+                    HistogramHistory::new(x, bl.age).take(n).collect()),
             };
             (values, timestamps)
         }),
@@ -163,6 +183,9 @@ pub fn extract_multi(value: &Value, bl: &Backlog, extract: &Extract)
                 => C::Integer(x.history(bl.age).take(num).collect()),
                 &B::Float(ref x)
                 => C::Float(x.history(bl.age).take(num).collect()),
+                &B::Histogram(ref bounds, ref x)
+                => C::Histogram(bounds.clone(),
+                    HistogramHistory::new(x, bl.age).take(num).collect()),
             };
             (values, timestamps)
         }),
@@ -26,12 +26,14 @@ pub enum MetricKind {
     Counter,
     Level,
     State,
+    Histogram,
 }
 
 probor_enum_encoder_decoder!(MetricKind {
     #0 Counter(),
     #1 Level(),
     #2 State(),
+    #3 Histogram(),
 });
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -78,6 +80,7 @@ pub enum Function {
     Sum(UndefFilter),
     SumBy(String, UndefFilter, bool),
     StateChart(/* limit of distinct values */ usize),
+    Percentile(/* percents, 0..100 */ u8),
 }
 
 probor_enum_encoder_decoder!(Function {
@@ -86,6 +89,7 @@ probor_enum_encoder_decoder!(Function {
     #2 Sum(undef_filter #1),
     #3 SumBy(field #1, undef_filter #2, total #3),
     #4 StateChart(distinct_num #1),
+    #5 Percentile(percent #1),
 });
 
 json_enum_decoder!(Function {
@@ -94,6 +98,7 @@ json_enum_decoder!(Function {
     Sum(undef_filter),
     SumBy(field, undef_filter, bool),
     StateChart(distinct_num),
+    Percentile(percent),
 });
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
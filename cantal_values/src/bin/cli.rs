@@ -3,17 +3,18 @@ extern crate cantal_values;
 
 use std::fmt::Display;
 use std::path::{Path, PathBuf};
-use std::io::{stderr, Write, Read, BufRead, BufReader};
+use std::env;
+use std::io::{stdout, stderr, Write, Read, BufRead, BufReader};
 use std::error::Error;
 use std::ffi::OsStr;
 use std::str::{FromStr, from_utf8};
 use std::process::exit;
 use std::os::unix::ffi::OsStrExt;
-use std::fs::File;
+use std::fs::{File, metadata};
 
 use argparse::{ArgumentParser, ParseList, Print};
 
-use cantal_values::Metadata;
+use cantal_values::{Metadata, MetadataError};
 
 
 fn read_file<D: Display>(prefix: D, path: &Path) -> Result<(), Box<Error>> {
@@ -82,13 +83,55 @@ fn read_from_pid(pid: u32) -> Result<(), Box<Error>> {
     Ok(())
 }
 
+fn lint_file(path: &Path) -> Result<(), Box<Error>> {
+    let meta = try!(Metadata::read_strict(&path.with_extension("meta")));
+    let size = try!(metadata(&path.with_extension("values"))).len();
+    if size != meta.values_size() as u64 {
+        return Err(Box::new(MetadataError::BadValuesSize(
+            meta.values_size() as u64, size)));
+    }
+    Ok(())
+}
+
+fn lint(args: Vec<String>) -> i32 {
+    let mut files = Vec::<PathBuf>::new();
+    {
+        let mut ap = ArgumentParser::new();
+        ap.set_description("Checks that metadata is valid and the size of
+            values file matches it. Prints nothing if files are okay.");
+        ap.refer(&mut files)
+            .add_argument("file", ParseList, "The `.meta` or `.values` file,
+            or list of files.").required();
+        match ap.parse(args, &mut stdout(), &mut stderr()) {
+            Ok(()) => {}
+            Err(x) => return x,
+        }
+    }
+    let mut retcode = 0;
+    for f in files.iter() {
+        if let Err(e) = lint_file(f) {
+            writeln!(&mut stderr(),
+                "{}: {}", f.with_extension("meta").display(), e).ok();
+            retcode = 1;
+        }
+    }
+    return retcode;
+}
+
 fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    if args.get(1).map(|x| &x[..] == "lint").unwrap_or(false) {
+        args.remove(1);
+        args[0].push_str(" lint");
+        exit(lint(args));
+    }
     let mut files = Vec::<PathBuf>::new();
     {
         let mut ap = ArgumentParser::new();
         ap.refer(&mut files)
             .add_argument("file_or_pid", ParseList, "Pid of the process,
-            or file name of the `.values` file, or list of files.");
+            or file name of the `.values` file, or list of files.
+            Use `lint` as the first argument to validate files instead.");
         ap.add_option(&["-V", "--version"],
             Print(env!("CARGO_PKG_VERSION").to_string()),
             "Show version and exit");
@@ -0,0 +1,669 @@
+//! The writer side of the memory-mapped protocol
+//!
+//! This is the same algorithm that is used in `cantal-py`: metrics are
+//! registered in a `Collection`, and when `start()` is called the layout is
+//! computed, the `.values` file is created and mapped into memory and the
+//! `.meta` file is atomically put in place. Handles returned from `add_*`
+//! methods may be cloned and shared between threads.
+//!
+//! Only available on 64-bit targets, as the values are updated with
+//! pointer-sized atomics.
+use std::io::{self, Write};
+use std::ptr;
+use std::mem::transmute;
+use std::ffi::OsString;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::fs::{File, OpenOptions, rename, remove_file};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, AtomicIsize, AtomicPtr, Ordering, fence};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::os::unix::io::AsRawFd;
+
+use libc;
+use rustc_serialize::json::Json;
+
+use LevelType;
+
+
+const STATE_HEADER_SIZE: usize = 8;
+
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum WriteError {
+        Io(err: io::Error) {
+            from()
+            description(err.description())
+            display("Error writing metrics: {}", err)
+            cause(err)
+        }
+        BadDescriptor {
+            description("Descriptor must be a json object")
+        }
+        Duplicate(name: String) {
+            description("Duplicate metric descriptor")
+            display("Metric {} is already defined", name)
+        }
+        BadSize(size: usize) {
+            description("Bad size of a state value")
+            display("Bad size of a state value: {}", size)
+        }
+        BadBuckets {
+            description("Histogram buckets must be strictly increasing")
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Counter,
+    Level(LevelType),
+    State(usize),
+    Histogram(Arc<Vec<u64>>),
+}
+
+struct Mapping {
+    ptr: *mut u8,
+    size: usize,
+}
+
+// Mapping is only unmapped on drop, and all accesses to the memory are
+// done using atomic operations (or are racy by protocol design, like states)
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+struct Slot {
+    ptr: AtomicPtr<u8>,
+    // Keeps memory mapped while any handle to the value is alive
+    mapping: Mutex<Option<Arc<Mapping>>>,
+}
+
+/// A handle to the ever-growing counter (`counter 8`)
+#[derive(Clone)]
+pub struct Counter(Arc<Slot>);
+
+/// A handle to a signed integer value (`level 8 signed`)
+#[derive(Clone)]
+pub struct Level(Arc<Slot>);
+
+/// A handle to a floating point value (`level 8 float`)
+#[derive(Clone)]
+pub struct FloatLevel(Arc<Slot>);
+
+/// A handle to the state value (`state N`)
+#[derive(Clone)]
+pub struct State(Arc<Slot>, usize);
+
+/// A handle to the histogram (`histogram N buckets=[..]`)
+#[derive(Clone)]
+pub struct Histogram(Arc<Slot>, Arc<Vec<u64>>);
+
+/// A handle to the generation counter (`generation 8`)
+///
+/// Cantal retries reading values file until it sees same even generation
+/// before and after reading, so updates wrapped into `update()` are seen
+/// either all at once or not at all.
+#[derive(Clone)]
+pub struct Generation(Arc<Slot>, Arc<Mutex<()>>);
+
+/// Marks the end of the update when dropped (even on panic)
+pub struct GenerationGuard<'a> {
+    ptr: Option<*mut u8>,
+    #[allow(dead_code)]
+    lock: MutexGuard<'a, ()>,
+}
+
+/// A collection of metrics that are not yet written to the disk
+pub struct Collection {
+    items: Vec<(String, Kind, Arc<Slot>)>,
+    names: HashSet<String>,
+    generation: Option<Arc<Slot>>,
+}
+
+/// A collection of metrics which files are already created
+///
+/// Dropping the object doesn't remove the files, so cantal is able to
+/// read last values, use `close()` to clean up.
+pub struct ActiveCollection {
+    path: PathBuf,
+    // Never read, but we keep values mapped while collection is alive
+    #[allow(dead_code)]
+    mapping: Option<Arc<Mapping>>,
+}
+
+fn add_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from)
+                   .unwrap_or_else(OsString::new);
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn remove_if_exists(path: &Path) -> Result<(), io::Error> {
+    match remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn time_ms() -> u64 {
+    let dur = SystemTime::now().duration_since(UNIX_EPOCH)
+        .expect("time is after epoch");
+    return dur.as_secs()*1000 + (dur.subsec_nanos() / 1_000_000) as u64;
+}
+
+impl Kind {
+    fn size(&self) -> usize {
+        match *self {
+            Kind::Counter => 8,
+            Kind::Level(_) => 8,
+            Kind::State(size) => size,
+            Kind::Histogram(ref bounds) => (bounds.len() + 1)*8,
+        }
+    }
+    fn type_name(&self) -> String {
+        match *self {
+            Kind::Counter => "counter 8".to_string(),
+            Kind::Level(LevelType::Signed) => "level 8 signed".to_string(),
+            Kind::Level(LevelType::Unsigned) => "level 8 unsigned".to_string(),
+            Kind::Level(LevelType::Float) => "level 8 float".to_string(),
+            Kind::State(size) => format!("state {}", size),
+            Kind::Histogram(ref bounds) => {
+                let items = bounds.iter().map(|x| x.to_string())
+                    .collect::<Vec<_>>();
+                format!("histogram {} buckets=[{}]",
+                    self.size(), items.join(","))
+            }
+        }
+    }
+}
+
+impl Mapping {
+    fn new(file: &File, size: usize) -> Result<Mapping, io::Error> {
+        let ptr = unsafe {
+            libc::mmap(ptr::null_mut(), size,
+                libc::PROT_READ|libc::PROT_WRITE, libc::MAP_SHARED,
+                file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            size: size,
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.size);
+        }
+    }
+}
+
+impl Slot {
+    fn new() -> Slot {
+        Slot {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            mapping: Mutex::new(None),
+        }
+    }
+    fn bind(&self, mapping: &Arc<Mapping>, offset: usize) {
+        *self.mapping.lock().expect("slot lock") = Some(mapping.clone());
+        self.ptr.store(unsafe { mapping.ptr.offset(offset as isize) },
+                       Ordering::Release);
+    }
+    /// Returns pointer to the value or None if collection is not started yet
+    fn get(&self) -> Option<*mut u8> {
+        let ptr = self.ptr.load(Ordering::Acquire);
+        if ptr.is_null() {
+            None
+        } else {
+            Some(ptr)
+        }
+    }
+}
+
+impl Counter {
+    pub fn incr(&self, value: u64) {
+        if let Some(ptr) = self.0.get() {
+            unsafe { &*(ptr as *const AtomicUsize) }
+                .fetch_add(value as usize, Ordering::Relaxed);
+        }
+    }
+    pub fn get(&self) -> u64 {
+        self.0.get().map(|ptr| {
+            unsafe { &*(ptr as *const AtomicUsize) }
+                .load(Ordering::Relaxed) as u64
+        }).unwrap_or(0)
+    }
+}
+
+impl Level {
+    pub fn set(&self, value: i64) {
+        if let Some(ptr) = self.0.get() {
+            unsafe { &*(ptr as *const AtomicIsize) }
+                .store(value as isize, Ordering::Relaxed);
+        }
+    }
+    pub fn incr(&self, value: i64) {
+        if let Some(ptr) = self.0.get() {
+            unsafe { &*(ptr as *const AtomicIsize) }
+                .fetch_add(value as isize, Ordering::Relaxed);
+        }
+    }
+    pub fn get(&self) -> i64 {
+        self.0.get().map(|ptr| {
+            unsafe { &*(ptr as *const AtomicIsize) }
+                .load(Ordering::Relaxed) as i64
+        }).unwrap_or(0)
+    }
+}
+
+impl FloatLevel {
+    pub fn set(&self, value: f64) {
+        if let Some(ptr) = self.0.get() {
+            let bits: u64 = unsafe { transmute(value) };
+            unsafe { &*(ptr as *const AtomicUsize) }
+                .store(bits as usize, Ordering::Relaxed);
+        }
+    }
+    pub fn get(&self) -> f64 {
+        self.0.get().map(|ptr| {
+            let bits = unsafe { &*(ptr as *const AtomicUsize) }
+                .load(Ordering::Relaxed) as u64;
+            unsafe { transmute::<u64, f64>(bits) }
+        }).unwrap_or(0.)
+    }
+}
+
+impl State {
+    /// Set the state, text is truncated to the size of the state
+    ///
+    /// Note: only a single thread should write any single state at a time
+    pub fn enter(&self, text: &str) {
+        if let Some(ptr) = self.0.get() {
+            let max = self.1 - STATE_HEADER_SIZE;
+            let bytes = text.as_bytes();
+            let len = if bytes.len() > max { max } else { bytes.len() };
+            unsafe {
+                let data = ptr.offset(STATE_HEADER_SIZE as isize);
+                ptr::copy_nonoverlapping(bytes.as_ptr(), data, len);
+                if len < max {
+                    *data.offset(len as isize) = 0;
+                }
+                (&*(ptr as *const AtomicUsize))
+                    .store(time_ms() as usize, Ordering::Release);
+            }
+        }
+    }
+    /// Reset the state timestamp to zero
+    pub fn exit(&self) {
+        if let Some(ptr) = self.0.get() {
+            unsafe { &*(ptr as *const AtomicUsize) }
+                .store(0, Ordering::Release);
+        }
+    }
+}
+
+impl Histogram {
+    /// Accounts a single value in the respective bucket
+    pub fn observe(&self, value: u64) {
+        if let Some(ptr) = self.0.get() {
+            let idx = self.1.iter().position(|&b| value <= b)
+                .unwrap_or(self.1.len());
+            unsafe { &*(ptr.offset((idx*8) as isize) as *const AtomicUsize) }
+                .fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    /// Bucket boundaries, the number of counters is one more
+    pub fn buckets(&self) -> &[u64] {
+        &self.1[..]
+    }
+}
+
+impl Generation {
+    /// Starts an update, it's finished when guard is dropped
+    ///
+    /// Updates are serialized between threads of the process
+    pub fn begin(&self) -> GenerationGuard {
+        let lock = self.1.lock().expect("generation lock");
+        let ptr = self.0.get();
+        if let Some(ptr) = ptr {
+            // Becomes odd, so readers know that update is in progress
+            unsafe { &*(ptr as *const AtomicUsize) }
+                .fetch_add(1, Ordering::Relaxed);
+            fence(Ordering::Release);
+        }
+        GenerationGuard {
+            ptr: ptr,
+            lock: lock,
+        }
+    }
+    /// Runs the function that updates multiple values consistently
+    pub fn update<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        let _guard = self.begin();
+        f()
+    }
+}
+
+impl<'a> Drop for GenerationGuard<'a> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            unsafe { &*(ptr as *const AtomicUsize) }
+                .fetch_add(1, Ordering::Release);
+        }
+    }
+}
+
+impl Collection {
+    pub fn new() -> Collection {
+        Collection {
+            items: Vec::new(),
+            names: HashSet::new(),
+            generation: None,
+        }
+    }
+    /// Adds a generation counter, should be used only once
+    ///
+    /// Note: agents older than v0.4.14 can't read files with a generation
+    /// counter, so it's not enabled by default.
+    pub fn add_generation(&mut self) -> Result<Generation, WriteError> {
+        if self.generation.is_some() {
+            return Err(WriteError::Duplicate("generation".to_string()));
+        }
+        let slot = Arc::new(Slot::new());
+        self.generation = Some(slot.clone());
+        Ok(Generation(slot, Arc::new(Mutex::new(()))))
+    }
+    fn add(&mut self, descriptor: &Json, kind: Kind)
+        -> Result<Arc<Slot>, WriteError>
+    {
+        if !descriptor.is_object() {
+            return Err(WriteError::BadDescriptor);
+        }
+        // Json objects are BTreeMaps, so serialization is canonical
+        let name = descriptor.to_string();
+        if self.names.contains(&name) {
+            return Err(WriteError::Duplicate(name));
+        }
+        self.names.insert(name.clone());
+        let slot = Arc::new(Slot::new());
+        self.items.push((name, kind, slot.clone()));
+        Ok(slot)
+    }
+    pub fn add_counter(&mut self, descriptor: &Json)
+        -> Result<Counter, WriteError>
+    {
+        self.add(descriptor, Kind::Counter).map(Counter)
+    }
+    pub fn add_level(&mut self, descriptor: &Json)
+        -> Result<Level, WriteError>
+    {
+        self.add(descriptor, Kind::Level(LevelType::Signed)).map(Level)
+    }
+    pub fn add_float_level(&mut self, descriptor: &Json)
+        -> Result<FloatLevel, WriteError>
+    {
+        self.add(descriptor, Kind::Level(LevelType::Float)).map(FloatLevel)
+    }
+    /// Adds a state, the `size` includes 8 bytes of the timestamp
+    ///
+    /// Size should be a multiple of 64 (cache line size) or a smaller
+    /// power of two for better performance
+    pub fn add_state(&mut self, descriptor: &Json, size: usize)
+        -> Result<State, WriteError>
+    {
+        if size <= STATE_HEADER_SIZE || size > 65535 {
+            return Err(WriteError::BadSize(size));
+        }
+        self.add(descriptor, Kind::State(size)).map(|x| State(x, size))
+    }
+    /// Adds a histogram with specified upper boundaries of buckets
+    ///
+    /// An additional bucket is always added for values larger than
+    /// all the boundaries
+    pub fn add_histogram(&mut self, descriptor: &Json, buckets: &[u64])
+        -> Result<Histogram, WriteError>
+    {
+        if buckets.iter().zip(buckets.iter().skip(1)).any(|(a, b)| a >= b) {
+            return Err(WriteError::BadBuckets);
+        }
+        let bounds = Arc::new(buckets.to_vec());
+        self.add(descriptor, Kind::Histogram(bounds.clone()))
+            .map(|x| Histogram(x, bounds))
+    }
+    /// Returns lines of the metadata file and offsets for each item
+    fn layout(&mut self) -> (Vec<String>, Vec<usize>, usize) {
+        self.items.sort_by(|&(ref aname, ref akind, _),
+                            &(ref bname, ref bkind, _)| {
+            (akind.size(), aname).cmp(&(bkind.size(), bname))
+        });
+        let mut offset = 0;
+        let mut scheme = Vec::with_capacity(self.items.len() + 1);
+        let mut offsets = Vec::with_capacity(self.items.len());
+        if self.generation.is_some() {
+            // Always put at offset zero
+            scheme.push("generation 8".to_string());
+            offset += 8;
+        }
+        for &(ref name, ref kind, _) in &self.items {
+            let size = kind.size();
+            let align = if size & (size - 1) == 0 {
+                // power of two, let's optimize
+                size
+            } else {
+                // unless value is small or it's size is crappy
+                // we must align to 8
+                8
+            };
+            if offset % align != 0 {
+                let pad = align - offset % align;
+                offset += pad;
+                scheme.push(format!("pad {}", pad));
+            }
+            offsets.push(offset);
+            offset += size;
+            scheme.push(format!("{}: {}", kind.type_name(), name));
+        }
+        return (scheme, offsets, offset);
+    }
+    /// Writes files and maps values into memory
+    ///
+    /// The `basepath` is the same as `CANTAL_PATH`, i.e. without extension
+    pub fn start<P: AsRef<Path>>(mut self, basepath: P)
+        -> Result<ActiveCollection, WriteError>
+    {
+        let base = basepath.as_ref();
+        let values_path = add_suffix(base, ".values");
+        let meta_path = add_suffix(base, ".meta");
+        let tmp_path = add_suffix(base, ".tmp");
+        let (scheme, offsets, size) = self.layout();
+
+        try!(remove_if_exists(&meta_path));
+        try!(remove_if_exists(&values_path));
+        try!(remove_if_exists(&tmp_path));
+
+        let mapping = {
+            let mut file = try!(OpenOptions::new()
+                .read(true).write(true).create(true).truncate(true)
+                .open(&tmp_path));
+            // We could use set_len, but our data is small and usually
+            // in RAM anyway
+            try!(file.write_all(&vec![0u8; size]));
+            try!(file.flush());
+            if size > 0 {
+                Some(Arc::new(try!(Mapping::new(&file, size))))
+            } else {
+                None
+            }
+        };
+        try!(rename(&tmp_path, &values_path));
+
+        {
+            let mut file = try!(File::create(&tmp_path));
+            for line in &scheme {
+                try!(writeln!(file, "{}", line));
+            }
+        }
+        try!(rename(&tmp_path, &meta_path));
+
+        if let Some(ref mapping) = mapping {
+            if let Some(ref slot) = self.generation {
+                slot.bind(mapping, 0);
+            }
+            for (&(_, _, ref slot), &offset) in
+                self.items.iter().zip(offsets.iter())
+            {
+                slot.bind(mapping, offset);
+            }
+        }
+        Ok(ActiveCollection {
+            path: base.to_path_buf(),
+            mapping: mapping,
+        })
+    }
+}
+
+impl ActiveCollection {
+    /// The base path of the collection (i.e. without extension)
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    /// Removes metadata and values files
+    ///
+    /// Handles are still valid after this call, but values written are
+    /// not visible to cantal any more
+    pub fn close(self) -> Result<(), io::Error> {
+        try!(remove_if_exists(&add_suffix(&self.path, ".meta")));
+        try!(remove_if_exists(&add_suffix(&self.path, ".values")));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Read;
+    use rustc_serialize::json::Json;
+    use {Metadata, Value};
+    use test_util::TestFiles;
+    use super::{Collection, add_suffix};
+
+    fn json(s: &str) -> Json {
+        Json::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let files = TestFiles::new("collection-layout");
+        let path = files.0.clone();
+        let mut coll = Collection::new();
+        coll.add_state(&json(r#"{"state": "x"}"#), 64).unwrap();
+        coll.add_counter(&json(r#"{"metric": "requests"}"#)).unwrap();
+        let active = coll.start(&path).unwrap();
+        let mut meta = String::new();
+        File::open(add_suffix(&path, ".meta")).unwrap()
+            .read_to_string(&mut meta).unwrap();
+        assert_eq!(meta, "counter 8: {\"metric\":\"requests\"}\n\
+                          pad 56\n\
+                          state 64: {\"state\":\"x\"}\n");
+        active.close().unwrap();
+    }
+
+    #[test]
+    fn generation() {
+        let files = TestFiles::new("collection-generation");
+        let path = files.0.clone();
+        let mut coll = Collection::new();
+        let gen = coll.add_generation().unwrap();
+        assert!(coll.add_generation().is_err());
+        let a = coll.add_counter(&json(r#"{"metric": "a"}"#)).unwrap();
+        let b = coll.add_counter(&json(r#"{"metric": "b"}"#)).unwrap();
+        let active = coll.start(&path).unwrap();
+        gen.update(|| {
+            a.incr(1);
+            b.incr(100);
+        });
+        let meta = Metadata::read(&add_suffix(&path, ".meta")).unwrap();
+        let data = meta.read_data(&add_suffix(&path, ".values")).unwrap();
+        assert_eq!(data.len(), 2);
+        match (&data[0].1, &data[1].1) {
+            (&Value::Counter(1), &Value::Counter(100)) => {}
+            x => panic!("Unexpected values {:?}", x),
+        }
+        active.close().unwrap();
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut coll = Collection::new();
+        coll.add_counter(&json(r#"{"metric": "a", "x": "y"}"#)).unwrap();
+        assert!(coll.add_level(&json(r#"{"x": "y", "metric": "a"}"#))
+            .is_err());
+        assert!(coll.add_level(&json(r#""metric""#)).is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let files = TestFiles::new("collection-roundtrip");
+        let path = files.0.clone();
+        let mut coll = Collection::new();
+        let cnt = coll.add_counter(&json(r#"{"metric": "cnt"}"#)).unwrap();
+        let lvl = coll.add_level(&json(r#"{"metric": "lvl"}"#)).unwrap();
+        let flt = coll.add_float_level(&json(r#"{"metric": "flt"}"#))
+            .unwrap();
+        let st = coll.add_state(&json(r#"{"state": "st"}"#), 32).unwrap();
+        let hist = coll.add_histogram(&json(r#"{"metric": "hist"}"#),
+            &[1, 10, 100]).unwrap();
+        let active = coll.start(&path).unwrap();
+        cnt.incr(10);
+        cnt.incr(5);
+        lvl.set(-7);
+        flt.set(1.5);
+        st.enter("hello");
+        hist.observe(0);
+        hist.observe(10);
+        hist.observe(50);
+        hist.observe(1000);
+        hist.observe(1000);
+        assert_eq!(cnt.get(), 15);
+
+        let meta = Metadata::read(&add_suffix(&path, ".meta")).unwrap();
+        let data = meta.read_data(&add_suffix(&path, ".values")).unwrap();
+        assert_eq!(data.len(), 5);
+        for &(ref desc, ref value) in &data {
+            match (desc.json.find("metric").and_then(|x| x.as_string()),
+                   value)
+            {
+                (Some("cnt"), &Value::Counter(15)) => {}
+                (Some("lvl"), &Value::Integer(-7)) => {}
+                (Some("flt"), &Value::Float(x)) if x == 1.5 => {}
+                (Some("hist"), &Value::Histogram(ref b, ref c)) => {
+                    assert_eq!(b, &vec![1, 10, 100]);
+                    assert_eq!(c, &vec![1, 1, 1, 2]);
+                }
+                (None, &Value::State((ts, ref text))) => {
+                    assert!(ts > 0);
+                    assert_eq!(text, "hello");
+                }
+                (m, v) => panic!("Unexpected value {:?}: {:?}", m, v),
+            }
+        }
+        st.exit();
+        let data = meta.read_data(&add_suffix(&path, ".values")).unwrap();
+        assert!(data.iter().any(|&(_, ref v)| matches_empty_state(v)));
+        active.close().unwrap();
+    }
+
+    fn matches_empty_state(value: &Value) -> bool {
+        match value {
+            &Value::State((0, _)) => true,
+            _ => false,
+        }
+    }
+}
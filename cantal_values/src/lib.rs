@@ -10,10 +10,13 @@ extern crate byteorder;
 
 use std::io::{Cursor, BufReader};
 use std::io::{Read, BufRead, Seek};
-use std::io::SeekFrom::{Current};
+use std::io::SeekFrom::{Current, Start};
 use std::io::Error as IoError;
+use std::i64;
 use std::fs::File;
-use std::rc::Rc;
+use std::sync::Arc;
+use std::collections::HashMap;
+use std::thread::yield_now;
 use std::path::Path;
 use std::error::Error;
 use std::convert::From;
@@ -21,11 +24,25 @@ use rustc_serialize::json;
 use rustc_serialize::json::{Json};
 use byteorder::{NativeEndian, ReadBytesExt};
 
-use itertools::NextValue;
-
 
 mod util;
+// 8-byte counters and levels are updated with `AtomicUsize` and
+// `AtomicIsize`, which are only 8 bytes wide on 64-bit targets
+#[cfg(target_pointer_width = "64")]
+mod collection;
 pub mod itertools;
+#[cfg(test)] mod test_util;
+
+/// Number of attempts to get a consistent snapshot of values file which
+/// has a generation counter
+const GENERATION_RETRIES: usize = 100;
+
+#[cfg(target_pointer_width = "64")]
+pub use collection::{Collection, ActiveCollection, WriteError};
+#[cfg(target_pointer_width = "64")]
+pub use collection::{Counter, Level, FloatLevel, State, Histogram};
+#[cfg(target_pointer_width = "64")]
+pub use collection::{Generation, GenerationGuard};
 
 
 #[derive(Clone, Debug, RustcEncodable, RustcDecodable)]
@@ -34,6 +51,9 @@ pub enum Value {
     Integer(i64),
     Float(f64),
     State((u64, String)),
+    /// Bucket boundaries and number of values in each bucket, the last
+    /// bucket counts values that are larger than all boundaries
+    Histogram(Vec<u64>, Vec<u64>),
 }
 
 probor_enum_encoder_decoder!(Value {
@@ -41,6 +61,7 @@ probor_enum_encoder_decoder!(Value {
     #1 Counter(value #1),
     #2 Integer(value #1),
     #3 Float(value #1),
+    #4 Histogram(bounds #1, counts #2),
 });
 
 #[derive(Debug, Clone, Copy)]
@@ -50,11 +71,13 @@ pub enum LevelType {
     Float,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Type {
     Counter(u8),
     Level(u8, LevelType),
     State(u16),
+    Histogram(u16, Vec<u64>),
+    Generation,
     Pad(u16),
     Unknown(u16),
 }
@@ -68,21 +91,40 @@ quick_error! {
             display("Error reading metadata: {}", err)
             cause(err)
         }
-        // TODO(tailhook) add line numbers
-        Json(err: json::ParserError) {
-            from()
+        Json(line: usize, column: usize, err: json::ParserError) {
             description(err.description())
-            display("Error parsing metadata json: {}", err)
+            display("line {}, column {}: Error parsing metadata json: {}",
+                    line, column, err)
             cause(err)
         }
-        ParseError(desc: &'static str) {
-            from()
+        ParseError(line: usize, column: usize, desc: &'static str) {
             description(desc)
-            display("Error parsing metadata: {}", desc)
+            display("line {}, column {}: Error parsing metadata: {}",
+                    line, column, desc)
         }
-        BadLength(len: usize) {
+        BadLength(line: usize, column: usize, len: usize) {
             description("Bad length of a field")
-            display("Bad length of a field")
+            display("line {}, column {}: Bad length of a field: {}",
+                    line, column, len)
+        }
+        UnknownType(line: usize, name: String) {
+            description("Unknown type of a field")
+            display("line {}: Unknown type of a field {:?}", line, name)
+        }
+        Duplicate(line: usize, previous: usize) {
+            description("Duplicate descriptor")
+            display("line {}: Duplicate descriptor, first one is at line {}",
+                    line, previous)
+        }
+        Misaligned(line: usize, offset: usize) {
+            description("Field is not aligned to 8 bytes")
+            display("line {}: Field at offset {} is not aligned to 8 bytes",
+                    line, offset)
+        }
+        BadValuesSize(expected: u64, actual: u64) {
+            description("Size of values file doesn't match metadata")
+            display("Values file is {} bytes, metadata describes {} bytes",
+                    actual, expected)
         }
         UnexpectedEOF {
             description("Unexpected end of file")
@@ -100,7 +142,9 @@ pub struct Descriptor {
 }
 
 pub struct Metadata {
-    items: Vec<Rc<Descriptor>>,
+    items: Vec<Arc<Descriptor>>,
+    /// Offset of the generation counter in the values file
+    generation: Option<usize>,
     stat: util::Stat,
 }
 
@@ -111,103 +155,151 @@ impl Value {
         match self {
             &Counter(_) | &Integer(_) | &Float(_) => 0,
             &State((_, ref v)) => v.as_bytes().len(),
+            &Histogram(ref b, ref c) => (b.len() + c.len())*8,
         }
     }
 }
 
 impl Metadata {
     pub fn read(path: &Path) -> Result<Metadata, MetadataError> {
-        // TODO(tailhook) implement LineNumberReader
+        Metadata::parse(path, false)
+    }
+    /// Reads metadata rejecting everything that `read` only tolerates
+    ///
+    /// This includes unknown types, sizes that can't be read, duplicate
+    /// descriptors and 8-byte values which are not aligned to 8 bytes.
+    pub fn read_strict(path: &Path) -> Result<Metadata, MetadataError> {
+        Metadata::parse(path, true)
+    }
+    fn parse(path: &Path, strict: bool) -> Result<Metadata, MetadataError> {
         let mut file = BufReader::new(try!(File::open(path)));
         let stat = try!(util::file_stat(file.get_ref()));
         let mut items = vec!();
+        let mut offset = 0;
+        let mut generation = None;
+        let mut lineno = 0;
+        let mut seen = HashMap::new();
         loop {
             let mut line = String::new();
             try!(file.read_line(&mut line));
             if line.len() == 0 { break; }
-            let mut pair = line.trim()[..].splitn(2, ':');
-            let mut type_iter = pair.next().unwrap().split(' ');
-            let typ = try!(type_iter.next()
-                .ok_or(MetadataError::ParseError("bad type name")));
-            let len: usize = try!(type_iter.next_value()
-                .map_err(|()| MetadataError::ParseError("bad length")));
-            let item = match typ {
-                "counter" => {
-                    if len > 255 {
-                        return Err(MetadataError::BadLength(len));
-                    }
-                    Type::Counter(len as u8)
-                }
-                "level" => {
-                    if len > 255 {
-                        return Err(MetadataError::BadLength(len));
+            lineno += 1;
+            let (kind, json) = try!(parse_line(lineno, &line, strict));
+            if strict && kind.alignment() == 8 && offset % 8 != 0 {
+                return Err(MetadataError::Misaligned(lineno, offset));
+            }
+            offset += kind.len();
+            let descriptor = match json {
+                Some((textname, json)) => {
+                    if strict {
+                        // serialized json has sorted keys, so it's the
+                        // same for all equal descriptors
+                        let norm = json.to_string();
+                        if let Some(&prev) = seen.get(&norm) {
+                            return Err(
+                                MetadataError::Duplicate(lineno, prev));
+                        }
+                        seen.insert(norm, lineno);
                     }
-                    let level_kind = match type_iter.next() {
-                        Some("signed") => LevelType::Signed,
-                        Some("unsigned") => LevelType::Unsigned,
-                        Some("float") => LevelType::Float,
-                        _ => return Err(MetadataError::ParseError(
-                            "bad kind of \"level\" variable")),
-                    };
-                    Type::Level(len as u8, level_kind)
-                }
-                "state" => {
-                    if len > 65535 {
-                        return Err(MetadataError::BadLength(len));
+                    Descriptor {
+                        textname: textname,
+                        json: json,
+                        kind: kind,
                     }
-                    Type::State(len as u16)
                 }
-                "pad" => {
-                    if len > 65535 {
-                        return Err(MetadataError::BadLength(len));
+                None => {
+                    if let Type::Generation = kind {
+                        if generation.is_some() {
+                            return Err(MetadataError::ParseError(lineno, 1,
+                                "duplicate generation counter"));
+                        }
+                        generation = Some(offset - 8);
                     }
-                    items.push(Rc::new(Descriptor {
+                    Descriptor {
                         textname: "".to_string(),
                         json: Json::Null,
-                        kind: Type::Pad(len as u16),
-                    }));
-                    continue;
-                }
-                _ => {
-                    if len > 65535 {
-                        return Err(MetadataError::BadLength(len));
+                        kind: kind,
                     }
-                    Type::Unknown(len as u16)
                 }
             };
-            let textname = try!(pair.next()
-                .ok_or(MetadataError::ParseError("No description for value")));
-            let json = try!(Json::from_str(textname));
-            items.push(Rc::new(Descriptor {
-                textname: textname.trim().to_string(),
-                json: json,
-                kind: item,
-            }));
+            items.push(Arc::new(descriptor));
         }
         return Ok(Metadata {
             items: items,
+            generation: generation,
             stat: stat,
         });
     }
+    /// Size of the values file as described by metadata
+    pub fn values_size(&self) -> usize {
+        self.items.iter().map(|x| x.kind.len()).sum()
+    }
+    /// Reads whole values file
+    ///
+    /// If there is a generation counter, retries until generation is even
+    /// and doesn't change while the file is read. If it's not stable after
+    /// several attempts, last read data is used (same as for files without
+    /// generation counter)
+    fn read_buffer(&self, path: &Path) -> Result<Vec<u8>, MetadataError> {
+        let mut file = try!(File::open(path));
+        let mut buf = Vec::with_capacity(4096);
+        if let Some(offset) = self.generation {
+            for _ in 0..GENERATION_RETRIES {
+                try!(file.seek(Start(offset as u64)));
+                let before = try!(file.read_u64::<NativeEndian>());
+                if before & 1 == 1 {
+                    // Writer is in the middle of an update
+                    yield_now();
+                    continue;
+                }
+                buf.clear();
+                try!(file.seek(Start(0)));
+                try!(file.read_to_end(&mut buf));
+                try!(file.seek(Start(offset as u64)));
+                let after = try!(file.read_u64::<NativeEndian>());
+                if before == after {
+                    return Ok(buf);
+                }
+            }
+            debug!("Generation of {:?} is unstable, values may be torn",
+                path);
+            buf.clear();
+            try!(file.seek(Start(0)));
+        }
+        try!(file.read_to_end(&mut buf));
+        return Ok(buf);
+    }
     pub fn read_data(&self, path: &Path)
-        -> Result<Vec<(Rc<Descriptor>, Value)>, MetadataError>
+        -> Result<Vec<(Arc<Descriptor>, Value)>, MetadataError>
     {
         //  We should read as fast as possible to have more precise results
         //  So we buffer whole file
         // TODO(tailhook) calculate the size of the file when reading metadata
-        let mut buf = Vec::with_capacity(4096);
-        try!(File::open(path)
-            .and_then(|mut f| f.read_to_end(&mut buf)));
+        let buf = try!(self.read_buffer(path));
 
         let mut stream = Cursor::new(buf);
         let mut res = vec!();
         for desc in self.items.iter() {
             let data = match desc.kind {
-                Type::Counter(8) => {
-                    Value::Counter(try!(stream.read_u64::<NativeEndian>()))
+                Type::Counter(len) if int_width(len) => {
+                    Value::Counter(try!(read_unsigned(&mut stream, len)))
                 }
-                Type::Level(8, LevelType::Signed) => {
-                    Value::Integer(try!(stream.read_i64::<NativeEndian>()))
+                Type::Level(len, LevelType::Signed) if int_width(len) => {
+                    Value::Integer(try!(read_signed(&mut stream, len)))
+                }
+                Type::Level(len, LevelType::Unsigned) if int_width(len) => {
+                    let val = try!(read_unsigned(&mut stream, len));
+                    // Values larger than 2^63 are very unlikely for levels,
+                    // so we saturate instead of switching the value type
+                    if val > i64::MAX as u64 {
+                        Value::Integer(i64::MAX)
+                    } else {
+                        Value::Integer(val as i64)
+                    }
+                }
+                Type::Level(4, LevelType::Float) => {
+                    let val = try!(stream.read_f32::<NativeEndian>());
+                    Value::Float(val as f64)
                 }
                 Type::Level(8, LevelType::Float) => {
                     Value::Float(try!(stream.read_f64::<NativeEndian>()))
@@ -231,11 +323,22 @@ impl Metadata {
                     try!(stream.seek(Current((len-8) as i64)));
                     Value::State((time_ms, text))
                 }
+                Type::Histogram(_, ref bounds) => {
+                    let mut counts = Vec::with_capacity(bounds.len() + 1);
+                    for _ in 0..bounds.len() + 1 {
+                        counts.push(try!(stream.read_u64::<NativeEndian>()));
+                    }
+                    Value::Histogram(bounds.clone(), counts)
+                }
                 Type::Pad(x) => {
                     try!(stream.seek(Current(x as i64)));
                     continue;
                 }
-                x => {
+                Type::Generation => {
+                    try!(stream.seek(Current(8)));
+                    continue;
+                }
+                ref x => {
                     warn!("Type {:?} cannot be read", x);
                     try!(stream.seek(Current(x.len() as i64)));
                     continue;
@@ -252,13 +355,398 @@ impl Metadata {
 }
 
 impl Type {
+    /// Alignment required for the field in strict mode, the only strict
+    /// requirement is that 8-byte values are aligned to 8 bytes
+    fn alignment(&self) -> usize {
+        match *self {
+            Type::Counter(8) | Type::Level(8, _) => 8,
+            // the state starts with 8-byte timestamp
+            Type::State(_) | Type::Histogram(_, _) | Type::Generation => 8,
+            _ => 1,
+        }
+    }
     fn len(&self) -> usize {
         match *self {
             Type::Counter(len) => len as usize,
             Type::Level(len, _) => len as usize,
             Type::State(len) => len as usize,
+            Type::Histogram(len, _) => len as usize,
+            Type::Generation => 8,
             Type::Pad(len) => len as usize,
             Type::Unknown(len) => len as usize,
         }
     }
 }
+
+fn int_width(len: u8) -> bool {
+    match len {
+        1 | 2 | 4 | 8 => true,
+        _ => false,
+    }
+}
+
+fn read_unsigned<R: Read>(stream: &mut R, len: u8) -> Result<u64, IoError> {
+    match len {
+        1 => stream.read_u8().map(|x| x as u64),
+        2 => stream.read_u16::<NativeEndian>().map(|x| x as u64),
+        4 => stream.read_u32::<NativeEndian>().map(|x| x as u64),
+        8 => stream.read_u64::<NativeEndian>(),
+        _ => unreachable!(),
+    }
+}
+
+fn read_signed<R: Read>(stream: &mut R, len: u8) -> Result<i64, IoError> {
+    match len {
+        1 => stream.read_i8().map(|x| x as i64),
+        2 => stream.read_i16::<NativeEndian>().map(|x| x as i64),
+        4 => stream.read_i32::<NativeEndian>().map(|x| x as i64),
+        8 => stream.read_i64::<NativeEndian>(),
+        _ => unreachable!(),
+    }
+}
+
+/// Parses a single line of metadata file
+///
+/// Returns type and, for value fields, a text and parsed json of the
+/// descriptor. Columns in errors are counted from one.
+fn parse_line(lineno: usize, line: &str, strict: bool)
+    -> Result<(Type, Option<(String, Json)>), MetadataError>
+{
+    use MetadataError::{ParseError, BadLength, UnknownType};
+    let line = line.trim_right();
+    let indent = line.len() - line.trim_left().len();
+    let line = line.trim_left();
+    let mut pair = line.splitn(2, ':');
+    let head = pair.next().unwrap();
+    let mut tokens = head.split(' ').scan(indent + 1, |col, tok| {
+        let start = *col;
+        *col += tok.len() + 1;
+        Some((start, tok))
+    });
+    let (_, typ) = tokens.next().unwrap();
+    if typ.len() == 0 {
+        return Err(ParseError(lineno, indent+1, "bad type name"));
+    }
+    let (len_col, len_str) = try!(tokens.next()
+        .ok_or(ParseError(lineno, indent+typ.len()+1, "bad length")));
+    let len: usize = try!(len_str.parse()
+        .map_err(|_| ParseError(lineno, len_col, "bad length")));
+    let max_len = match typ {
+        "counter" | "level" => 255,
+        "generation" => 8,
+        _ => 65535,
+    };
+    if len > max_len {
+        return Err(BadLength(lineno, len_col, len));
+    }
+    let item = match typ {
+        "counter" => {
+            if strict && !int_width(len as u8) {
+                return Err(BadLength(lineno, len_col, len));
+            }
+            Type::Counter(len as u8)
+        }
+        "level" => {
+            let level_kind = match tokens.next() {
+                Some((_, "signed")) => LevelType::Signed,
+                Some((_, "unsigned")) => LevelType::Unsigned,
+                Some((_, "float")) => LevelType::Float,
+                Some((col, _)) => return Err(ParseError(lineno, col,
+                    "bad kind of \"level\" variable")),
+                None => return Err(ParseError(lineno, head.len()+indent+1,
+                    "bad kind of \"level\" variable")),
+            };
+            let readable = match level_kind {
+                LevelType::Float => len == 4 || len == 8,
+                _ => int_width(len as u8),
+            };
+            if strict && !readable {
+                return Err(BadLength(lineno, len_col, len));
+            }
+            Type::Level(len as u8, level_kind)
+        }
+        "state" => {
+            if strict && len <= 8 {
+                return Err(BadLength(lineno, len_col, len));
+            }
+            Type::State(len as u16)
+        }
+        "histogram" => {
+            let (col, buckets) = try!(tokens.next()
+                .ok_or(ParseError(lineno, head.len()+indent+1,
+                    "bad buckets of \"histogram\" variable")));
+            let bounds = try!(parse_buckets(buckets)
+                .ok_or(ParseError(lineno, col,
+                    "bad buckets of \"histogram\" variable")));
+            if len != (bounds.len() + 1)*8 {
+                return Err(BadLength(lineno, len_col, len));
+            }
+            Type::Histogram(len as u16, bounds)
+        }
+        "pad" => return Ok((Type::Pad(len as u16), None)),
+        "generation" => {
+            if len != 8 {
+                return Err(BadLength(lineno, len_col, len));
+            }
+            return Ok((Type::Generation, None));
+        }
+        _ => {
+            if strict {
+                return Err(UnknownType(lineno, typ.to_string()));
+            }
+            Type::Unknown(len as u16)
+        }
+    };
+    let textname = try!(pair.next()
+        .ok_or(ParseError(lineno, line.len()+indent+1,
+                          "No description for value")));
+    // column of the first character after the colon
+    let json_col = indent + head.len() + 2;
+    let json = try!(Json::from_str(textname).map_err(|e| {
+        let column = match e {
+            json::ParserError::SyntaxError(_, _, col) => json_col + col - 1,
+            _ => json_col,
+        };
+        MetadataError::Json(lineno, column, e)
+    }));
+    Ok((item, Some((textname.trim().to_string(), json))))
+}
+
+/// Parses `buckets=[1,5,10]`, boundaries must be strictly increasing
+fn parse_buckets(val: &str) -> Option<Vec<u64>> {
+    if !val.starts_with("buckets=[") || !val.ends_with("]") {
+        return None;
+    }
+    let inner = &val["buckets=[".len()..val.len()-1];
+    if inner.len() == 0 {
+        return Some(Vec::new());
+    }
+    let mut result = Vec::new();
+    for item in inner.split(',') {
+        match item.parse() {
+            Ok(x) => {
+                if result.last().map(|&prev| prev >= x).unwrap_or(false) {
+                    return None;
+                }
+                result.push(x);
+            }
+            Err(_) => return None,
+        }
+    }
+    return Some(result);
+}
+
+#[cfg(test)]
+mod test {
+    use {Metadata, MetadataError, Value};
+    use test_util::TestFiles;
+
+    fn read(name: &str, meta: &str, values: &[u8]) -> Vec<Value> {
+        let files = TestFiles::new(name);
+        files.write(meta, values);
+        let meta = Metadata::read(&files.meta()).unwrap();
+        meta.read_data(&files.values()).unwrap()
+            .into_iter().map(|(_, v)| v).collect()
+    }
+
+    fn read_meta(name: &str, meta: &str, strict: bool)
+        -> Result<Metadata, MetadataError>
+    {
+        let files = TestFiles::new(name);
+        files.write(meta, b"");
+        if strict {
+            Metadata::read_strict(&files.meta())
+        } else {
+            Metadata::read(&files.meta())
+        }
+    }
+
+    fn assert_counter(v: &Value, x: u64) {
+        match v {
+            &Value::Counter(y) if x == y => {}
+            _ => panic!("Expected counter {}, got {:?}", x, v),
+        }
+    }
+
+    fn assert_integer(v: &Value, x: i64) {
+        match v {
+            &Value::Integer(y) if x == y => {}
+            _ => panic!("Expected integer {}, got {:?}", x, v),
+        }
+    }
+
+    #[test]
+    fn counters() {
+        let mut values = Vec::new();
+        values.extend(&[0xFF]);
+        values.extend(&[0; 1]);
+        values.extend(&unsafe { ::std::mem::transmute::<u16, [u8; 2]>(
+            0xFFFE) });
+        values.extend(&unsafe { ::std::mem::transmute::<u32, [u8; 4]>(
+            0xFFFFFFFD) });
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            0xFFFFFFFFFFFFFFFC) });
+        let data = read("counters", "counter 1: {\"metric\": \"c1\"}\n\
+                                     pad 1\n\
+                                     counter 2: {\"metric\": \"c2\"}\n\
+                                     counter 4: {\"metric\": \"c4\"}\n\
+                                     counter 8: {\"metric\": \"c8\"}\n",
+                        &values);
+        assert_eq!(data.len(), 4);
+        assert_counter(&data[0], 0xFF);
+        assert_counter(&data[1], 0xFFFE);
+        assert_counter(&data[2], 0xFFFFFFFD);
+        assert_counter(&data[3], 0xFFFFFFFFFFFFFFFC);
+    }
+
+    #[test]
+    fn signed_levels() {
+        let mut values = Vec::new();
+        values.extend(&[0xFF, 0]);
+        values.extend(&unsafe { ::std::mem::transmute::<i16, [u8; 2]>(
+            -2) });
+        values.extend(&unsafe { ::std::mem::transmute::<i32, [u8; 4]>(
+            -3) });
+        values.extend(&unsafe { ::std::mem::transmute::<i64, [u8; 8]>(
+            -4) });
+        let data = read("signed", "level 1 signed: {\"metric\": \"l1\"}\n\
+                                   pad 1\n\
+                                   level 2 signed: {\"metric\": \"l2\"}\n\
+                                   level 4 signed: {\"metric\": \"l4\"}\n\
+                                   level 8 signed: {\"metric\": \"l8\"}\n",
+                        &values);
+        assert_eq!(data.len(), 4);
+        assert_integer(&data[0], -1);
+        assert_integer(&data[1], -2);
+        assert_integer(&data[2], -3);
+        assert_integer(&data[3], -4);
+    }
+
+    #[test]
+    fn unsigned_levels() {
+        let mut values = Vec::new();
+        values.extend(&[0xFF, 0]);
+        values.extend(&unsafe { ::std::mem::transmute::<u16, [u8; 2]>(
+            0xFFFF) });
+        values.extend(&unsafe { ::std::mem::transmute::<u32, [u8; 4]>(
+            0xFFFFFFFF) });
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            12345) });
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            0xFFFFFFFFFFFFFFFF) });
+        let data = read("unsigned",
+            "level 1 unsigned: {\"metric\": \"l1\"}\n\
+             pad 1\n\
+             level 2 unsigned: {\"metric\": \"l2\"}\n\
+             level 4 unsigned: {\"metric\": \"l4\"}\n\
+             level 8 unsigned: {\"metric\": \"l8\"}\n\
+             level 8 unsigned: {\"metric\": \"big\"}\n",
+            &values);
+        assert_eq!(data.len(), 5);
+        assert_integer(&data[0], 0xFF);
+        assert_integer(&data[1], 0xFFFF);
+        assert_integer(&data[2], 0xFFFFFFFF);
+        assert_integer(&data[3], 12345);
+        assert_integer(&data[4], ::std::i64::MAX);
+    }
+
+    #[test]
+    fn generation() {
+        let mut values = Vec::new();
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            2) });
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            7) });
+        let data = read("generation", "generation 8\n\
+                                       counter 8: {\"metric\": \"c\"}\n",
+                        &values);
+        assert_eq!(data.len(), 1);
+        assert_counter(&data[0], 7);
+    }
+
+    #[test]
+    fn unstable_generation() {
+        // Odd generation is never stable, but we get data anyway
+        let mut values = Vec::new();
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            5) });
+        values.extend(&unsafe { ::std::mem::transmute::<u64, [u8; 8]>(
+            3) });
+        let data = read("unstable", "counter 8: {\"metric\": \"c\"}\n\
+                                     generation 8\n",
+                        &values);
+        assert_eq!(data.len(), 1);
+        assert_counter(&data[0], 5);
+    }
+
+    #[test]
+    fn float_levels() {
+        let mut values = Vec::new();
+        values.extend(&unsafe { ::std::mem::transmute::<f32, [u8; 4]>(
+            1.5) });
+        values.extend(&[0; 4]);
+        values.extend(&unsafe { ::std::mem::transmute::<f64, [u8; 8]>(
+            -2.25) });
+        let data = read("floats", "level 4 float: {\"metric\": \"f4\"}\n\
+                                   pad 4\n\
+                                   level 8 float: {\"metric\": \"f8\"}\n",
+                        &values);
+        assert_eq!(data.len(), 2);
+        match (&data[0], &data[1]) {
+            (&Value::Float(a), &Value::Float(b)) => {
+                assert_eq!(a, 1.5);
+                assert_eq!(b, -2.25);
+            }
+            x => panic!("Expected floats, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn error_positions() {
+        match read_meta("badlen", "counter 8: {\"metric\": \"a\"}\n\
+                                   counter x: {\"metric\": \"b\"}\n", false) {
+            Err(MetadataError::ParseError(2, 9, _)) => {}
+            Err(e) => panic!("Wrong error {}", e),
+            Ok(_) => panic!("Error expected"),
+        }
+        match read_meta("badjson", "counter 8: {\"metric\": \"a\"}\n\
+                                    level 8 signed: {\"metric\" 1}\n",
+                        false) {
+            Err(MetadataError::Json(2, col, _)) => assert!(col > 16),
+            Err(e) => panic!("Wrong error {}", e),
+            Ok(_) => panic!("Error expected"),
+        }
+    }
+
+    #[test]
+    fn strict() {
+        let dup = "counter 8: {\"metric\": \"a\", \"x\": \"1\"}\n\
+                   counter 8: {\"x\": \"1\", \"metric\": \"a\"}\n";
+        assert!(read_meta("dup", dup, false).is_ok());
+        match read_meta("dup", dup, true) {
+            Err(MetadataError::Duplicate(2, 1)) => {}
+            Err(e) => panic!("Wrong error {}", e),
+            Ok(_) => panic!("Error expected"),
+        }
+        let misaligned = "counter 4: {\"metric\": \"a\"}\n\
+                          counter 8: {\"metric\": \"b\"}\n";
+        assert!(read_meta("misaligned", misaligned, false).is_ok());
+        match read_meta("misaligned", misaligned, true) {
+            Err(MetadataError::Misaligned(2, 4)) => {}
+            Err(e) => panic!("Wrong error {}", e),
+            Ok(_) => panic!("Error expected"),
+        }
+        let unknown = "gauge 8: {\"metric\": \"a\"}\n";
+        assert!(read_meta("unknown", unknown, false).is_ok());
+        match read_meta("unknown", unknown, true) {
+            Err(MetadataError::UnknownType(1, ref x)) if x == "gauge" => {}
+            Err(e) => panic!("Wrong error {}", e),
+            Ok(_) => panic!("Error expected"),
+        }
+        let good = read_meta("good", "counter 4: {\"metric\": \"a\"}\n\
+                                      pad 4\n\
+                                      counter 8: {\"metric\": \"b\"}\n",
+                             true).unwrap();
+        assert_eq!(good.values_size(), 16);
+    }
+}
@@ -0,0 +1,42 @@
+//! Helpers shared by tests
+use std::env;
+use std::fs::{File, remove_file};
+use std::io::Write;
+use std::path::PathBuf;
+
+use libc::getpid;
+
+
+/// Base path of `.meta` and `.values` files in the temporary directory
+///
+/// Files are removed when the value is dropped.
+pub struct TestFiles(pub PathBuf);
+
+impl TestFiles {
+    /// Tests run in parallel in the same process, so `name` must be unique
+    /// among all the tests, e.g. prefixed with the module name
+    pub fn new(name: &str) -> TestFiles {
+        // no dots in the name, so that we can use with_extension()
+        TestFiles(env::temp_dir().join(format!("cantal-test-{}-{}",
+            unsafe { getpid() }, name)))
+    }
+    pub fn meta(&self) -> PathBuf {
+        self.0.with_extension("meta")
+    }
+    pub fn values(&self) -> PathBuf {
+        self.0.with_extension("values")
+    }
+    pub fn write(&self, meta: &str, values: &[u8]) {
+        File::create(self.meta()).unwrap()
+            .write_all(meta.as_bytes()).unwrap();
+        File::create(self.values()).unwrap()
+            .write_all(values).unwrap();
+    }
+}
+
+impl Drop for TestFiles {
+    fn drop(&mut self) {
+        remove_file(self.meta()).ok();
+        remove_file(self.values()).ok();
+    }
+}
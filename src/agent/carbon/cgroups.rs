@@ -21,6 +21,14 @@ struct CGroup {
     system_cpu: f64,
     read_bytes: f64,
     write_bytes: f64,
+    fds: u64,
+    swap: u64,
+    minor_faults: f64,
+    major_faults: f64,
+    voluntary_ctx_switches: f64,
+    involuntary_ctx_switches: f64,
+    /// Maximum over processes, summing scores makes no sense
+    oom_score: u64,
     user_metrics: HashMap<String, f64>,
 }
 
@@ -44,7 +52,17 @@ pub fn scan(sender: &mut Sender, cfg: &Config, stats: &Stats)
             key.get_with("metric", |metric| {
                 let grp = cgroups.entry(cgroup.to_owned())
                     .or_insert_with(CGroup::new);
+                // Process metrics are summed by the collector in keys
+                // without pid, they include exited processes too
+                let per_pid = key.get_with("pid", |_| ()).is_some();
                 match metric {
+                    _ if per_pid => {}
+                    "processes" => {
+                        if let Some(Integer(val)) = value.tip_or_none(cut_age)
+                        {
+                            grp.num_processes += val as u64;
+                        }
+                    }
                     "vsize" => {
                         if let Some(Integer(val)) = value.tip_or_none(cut_age)
                         {
@@ -61,7 +79,6 @@ pub fn scan(sender: &mut Sender, cfg: &Config, stats: &Stats)
                         if let Some(Integer(val)) = value.tip_or_none(cut_age)
                         {
                             grp.num_threads += val as u64;
-                            grp.num_processes += 1;
                         }
                     }
                     "user_time" => {
@@ -94,6 +111,40 @@ pub fn scan(sender: &mut Sender, cfg: &Config, stats: &Stats)
                             grp.write_bytes += (value as f64)*1000.0/mfloat;
                         }
                     }
+                    "fds" => {
+                        if let Some(Integer(val)) = value.tip_or_none(cut_age)
+                        {
+                            grp.fds += val as u64;
+                        }
+                    }
+                    "swap" => {
+                        if let Some(Integer(val)) = value.tip_or_none(cut_age)
+                        {
+                            grp.swap += val as u64;
+                        }
+                    }
+                    "oom_score" => {
+                        if let Some(Integer(val)) = value.tip_or_none(cut_age)
+                        {
+                            grp.oom_score = val as u64;
+                        }
+                    }
+                    "minor_faults" | "major_faults" |
+                    "voluntary_ctx_switches" | "involuntary_ctx_switches"
+                    => {
+                        let diff = get_counter_diff(value, backlog, num);
+                        if let Some((value, millis)) = diff {
+                            let rate = (value as f64)*1000.0/(millis as f64);
+                            match metric {
+                                "minor_faults" => grp.minor_faults += rate,
+                                "major_faults" => grp.major_faults += rate,
+                                "voluntary_ctx_switches" => {
+                                    grp.voluntary_ctx_switches += rate;
+                                }
+                                _ => grp.involuntary_ctx_switches += rate,
+                            }
+                        }
+                    }
                     _ => {}
                 }
                 key.get_with("state", |statename| {
@@ -152,6 +203,36 @@ pub fn scan(sender: &mut Sender, cfg: &Config, stats: &Stats)
             format_args!("cantal.{}.{}.cgroups.{}.write_bps",
                 cls, stats.hostname, name),
             cgroup.write_bytes, unixtime);
+        sender.add_value_at(
+            format_args!("cantal.{}.{}.cgroups.{}.fds",
+                cls, stats.hostname, name),
+            cgroup.fds, unixtime);
+        sender.add_value_at(
+            format_args!("cantal.{}.{}.cgroups.{}.swap",
+                cls, stats.hostname, name),
+            cgroup.swap, unixtime);
+        sender.add_value_at(
+            format_args!("cantal.{}.{}.cgroups.{}.oom_score",
+                cls, stats.hostname, name),
+            cgroup.oom_score, unixtime);
+        sender.add_value_at(
+            format_args!("cantal.{}.{}.cgroups.{}.minor_faults_per_sec",
+                cls, stats.hostname, name),
+            cgroup.minor_faults, unixtime);
+        sender.add_value_at(
+            format_args!("cantal.{}.{}.cgroups.{}.major_faults_per_sec",
+                cls, stats.hostname, name),
+            cgroup.major_faults, unixtime);
+        sender.add_value_at(
+            format_args!(
+                "cantal.{}.{}.cgroups.{}.voluntary_ctx_switches_per_sec",
+                cls, stats.hostname, name),
+            cgroup.voluntary_ctx_switches, unixtime);
+        sender.add_value_at(
+            format_args!(
+                "cantal.{}.{}.cgroups.{}.involuntary_ctx_switches_per_sec",
+                cls, stats.hostname, name),
+            cgroup.involuntary_ctx_switches, unixtime);
         for (key, value) in cgroup.user_metrics {
             sender.add_value_at(
                 format_args!("cantal.{}.{}.cgroups.{}.{}",
@@ -172,6 +253,13 @@ impl CGroup {
             system_cpu: 0.,
             read_bytes: 0.,
             write_bytes: 0.,
+            fds: 0,
+            swap: 0,
+            minor_faults: 0.,
+            major_faults: 0.,
+            voluntary_ctx_switches: 0.,
+            involuntary_ctx_switches: 0.,
+            oom_score: 0,
             user_metrics: HashMap::new(),
         }
     }
@@ -0,0 +1,664 @@
+use std::thread;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, TryRecvError, RecvTimeoutError};
+use std::collections::{HashMap, BTreeMap};
+
+use quire::validate::{Structure, Mapping, Scalar, Numeric};
+
+use cantal::Value;
+use history::Key;
+use deps::Dependencies;
+use configs::Configs;
+use statsd;
+use prometheus;
+use scripts;
+use scan::{Tip, Roots, time_ms};
+use scan::{machine, filesystems, interfaces, sensors};
+use scan::{cgroups, cgroup_stats, processes, connections, values};
+use scan::groups;
+use scan::cgroups::CGroups;
+use scan::processes::MinimalProcess;
+use scan::connections::Connections;
+
+
+/// Number of intervals the collector is postponed for when it times out
+const TIMEOUT_BACKOFF: u64 = 10;
+
+
+/// Per-collector settings, read from `*.collectors.yaml`
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Config {
+    pub enabled: bool,
+    /// Milliseconds, runs on every scan if not set
+    pub interval: Option<u32>,
+    /// Milliseconds, if set the collector runs in a separate thread, and
+    /// its results are dropped if it takes longer
+    pub timeout: Option<u32>,
+}
+
+pub fn validator<'x>() -> Mapping<'x> {
+    Mapping::new(Scalar::new(), Structure::new()
+        .member("enabled", Scalar::new().default(true))
+        .member("interval", Numeric::new().min(1).max(86400000).optional())
+        .member("timeout", Numeric::new().min(1).max(86400000).optional()))
+}
+
+/// Results of collectors that are used by other collectors
+///
+/// The state is kept between scans, so a collector that runs more often
+/// than its dependencies uses the results of their last run.
+pub struct State {
+    pub roots: Roots,
+    pub boot_time: Option<u64>,
+    pub cgroups: CGroups,
+    /// Shared with the scanner, which keeps them for the web interface
+    pub processes: Arc<Vec<MinimalProcess>>,
+    /// Environment of the processes, read by the processes collector
+    pub environ: values::Environs,
+    /// Put here by the connections collector, and taken by the scanner
+    pub connections: Option<Connections>,
+}
+
+/// Collectors having a `timeout` are moved to a thread to run, so they
+/// must be `Send`
+pub trait Collector: Send {
+    /// Name used in configs and in self-metrics
+    fn name(&self) -> &'static str;
+    fn collect(&mut self, tip: &mut Tip, state: &mut State);
+    /// Cumulative counters reported as `cantal.collector.<name>`
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+}
+
+/// Cost of the collector, shown in `/status.json`
+#[derive(RustcEncodable, Clone, Debug)]
+pub struct CollectorStats {
+    pub name: &'static str,
+    pub last_run: u64,
+    /// Milliseconds the last run took
+    pub duration: u32,
+    pub runs: u64,
+    pub counters: BTreeMap<&'static str, u64>,
+}
+
+/// Collector, its state and its metrics, returned by the worker thread
+type Finished = (Box<Collector>, State, Tip);
+
+struct Entry {
+    name: &'static str,
+    /// `None` while the collector is running in a worker that has timed
+    /// out, or if the collector has panicked there
+    collector: Option<Box<Collector>>,
+    /// Worker that has timed out, the collector is returned when it
+    /// finishes, its results are dropped
+    worker: Option<Receiver<Finished>>,
+    /// Counters of the collector after its last run
+    counters: Vec<(&'static str, u64)>,
+    config: Option<Config>,
+    next_run: u64,
+    /// Values of the last run, repeated when the collector is skipped,
+    /// only kept for collectors having an interval. Counters aren't kept,
+    /// as repeated counters show zero rate followed by a spike
+    last_values: Vec<(Key, Value)>,
+    last_run: u64,
+    last_duration: u32,
+    runs: u64,
+}
+
+/// Collectors in the order they run in
+pub struct Registry {
+    scan_interval: u64,
+    configs: HashMap<String, Config>,
+    entries: Vec<Entry>,
+}
+
+impl Registry {
+    pub fn new(scan_interval: u32, configs: &HashMap<String, Config>)
+        -> Registry
+    {
+        Registry {
+            scan_interval: scan_interval as u64,
+            configs: configs.clone(),
+            entries: Vec::new(),
+        }
+    }
+    /// Adds a collector unless it's disabled in config
+    ///
+    /// Collectors run in the order they are added in, so dependencies
+    /// must be added first.
+    pub fn add<C: Collector + 'static>(&mut self, collector: C) {
+        let config = self.configs.remove(collector.name());
+        if let Some(Config { enabled: false, .. }) = config {
+            info!("Collector {:?} is disabled", collector.name());
+            return;
+        }
+        self.entries.push(Entry {
+            name: collector.name(),
+            counters: collector.counters(),
+            collector: Some(Box::new(collector)),
+            worker: None,
+            config: config,
+            next_run: 0,
+            last_values: Vec::new(),
+            last_run: 0,
+            last_duration: 0,
+            runs: 0,
+        });
+    }
+    /// Warns about configs that don't match any collector
+    pub fn check_unused(&self) {
+        for name in self.configs.keys() {
+            warn!("Config for unknown collector {:?}", name);
+        }
+    }
+    pub fn stats(&self) -> Vec<CollectorStats> {
+        self.entries.iter().map(|entry| CollectorStats {
+            name: entry.name,
+            last_run: entry.last_run,
+            duration: entry.last_duration,
+            runs: entry.runs,
+            counters: entry.counters.iter().cloned().collect(),
+        }).collect()
+    }
+    /// Runs collectors which are due, and records their cost in the tip
+    pub fn collect(&mut self, tip: &mut Tip, state: &mut State, now: u64) {
+        // Scans are not exactly `scan_interval` apart, so the collector
+        // runs on the scan that is closest to the time it should run at
+        let now_margin = now + self.scan_interval / 2;
+        for entry in self.entries.iter_mut() {
+            let (interval, timeout) = match entry.config {
+                Some(ref cfg) => (cfg.interval, cfg.timeout),
+                None => (None, None),
+            };
+            if entry.next_run > now_margin || !entry.reclaim() {
+                for &(ref key, ref value) in entry.last_values.iter() {
+                    tip.add(key.clone(), value.clone());
+                }
+                write_self_metrics(tip, entry);
+                continue;
+            }
+            let start = time_ms();
+            let own = if let Some(timeout) = timeout {
+                entry.run_worker(state, timeout as u64)
+            } else if interval.is_some() {
+                let mut own = Tip::new();
+                entry.collector.as_mut().unwrap().collect(&mut own, state);
+                Some(own)
+            } else {
+                entry.collector.as_mut().unwrap().collect(tip, state);
+                None
+            };
+            let duration = time_ms().saturating_sub(start);
+            let has_interval = interval.is_some();
+            let interval = interval.map(|x| x as u64)
+                .unwrap_or(self.scan_interval);
+            entry.last_run = now;
+            entry.last_duration = duration as u32;
+            entry.runs += 1;
+            entry.next_run = now + interval;
+            match entry.collector {
+                Some(ref collector) => {
+                    entry.counters = collector.counters();
+                }
+                None if entry.worker.is_some() => {
+                    warn!("Collector {:?} timed out, postponing it for {} ms",
+                           entry.name, interval * TIMEOUT_BACKOFF);
+                    entry.next_run = now + interval * TIMEOUT_BACKOFF;
+                }
+                None => {}
+            }
+            if let Some(own) = own {
+                if has_interval {
+                    entry.last_values = own.map.iter()
+                        .filter(|&(_, v)| !matches!(*v, Value::Counter(_)))
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect();
+                }
+                tip.map.extend(own.map.into_iter());
+            } else if entry.collector.is_none() {
+                entry.last_values.clear();
+            }
+            debug!("Collector {:?} took {} ms", entry.name, duration);
+            write_self_metrics(tip, entry);
+        }
+    }
+}
+
+impl Entry {
+    /// Takes the collector back from the worker that has timed out
+    ///
+    /// Returns `false` if the collector can't run yet
+    fn reclaim(&mut self) -> bool {
+        let result = match self.worker {
+            Some(ref rx) => rx.try_recv(),
+            None => return self.collector.is_some(),
+        };
+        match result {
+            Ok((collector, _, _)) => {
+                info!("Collector {:?} has finished after timeout",
+                      self.name);
+                self.collector = Some(collector);
+                self.worker = None;
+                true
+            }
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                error!("Collector {:?} has panicked, it's disabled",
+                       self.name);
+                self.worker = None;
+                false
+            }
+        }
+    }
+    /// Runs the collector in a thread, on a copy of the state
+    ///
+    /// If it finishes in time, the state is updated and collected metrics
+    /// are returned. Otherwise, nothing is changed, and the collector is
+    /// left in the worker until it finishes.
+    fn run_worker(&mut self, state: &mut State, timeout: u64)
+        -> Option<Tip>
+    {
+        let mut collector = self.collector.take().unwrap();
+        // connections are only taken by the scanner, no need to copy them
+        let mut copy = State {
+            roots: state.roots.clone(),
+            boot_time: state.boot_time,
+            cgroups: state.cgroups.clone(),
+            processes: state.processes.clone(),
+            environ: state.environ.clone(),
+            connections: None,
+        };
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let mut own = Tip::new();
+            collector.collect(&mut own, &mut copy);
+            tx.send((collector, copy, own)).ok();
+        });
+        match rx.recv_timeout(Duration::from_millis(timeout)) {
+            Ok((collector, new_state, own)) => {
+                self.collector = Some(collector);
+                let connections = state.connections.take();
+                *state = new_state;
+                if state.connections.is_none() {
+                    state.connections = connections;
+                }
+                Some(own)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                self.worker = Some(rx);
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                error!("Collector {:?} has panicked, it's disabled",
+                       self.name);
+                None
+            }
+        }
+    }
+}
+
+/// Metrics are written on every scan, so they don't have gaps when the
+/// collector is skipped
+fn write_self_metrics(tip: &mut Tip, entry: &Entry) {
+    let name = entry.name;
+    let key = |metric: &str| Key::pairs(&[
+        ("collector", name),
+        ("metric", metric),
+    ]);
+    tip.add(key("cantal.collector.duration"),
+        Value::Integer(entry.last_duration as i64));
+    tip.add(key("cantal.collector.runs"), Value::Counter(entry.runs));
+    for &(counter, value) in entry.counters.iter() {
+        tip.add(key(&format!("cantal.collector.{}", counter)[..]),
+            Value::Counter(value));
+    }
+}
+
+struct Machine;
+
+impl Collector for Machine {
+    fn name(&self) -> &'static str { "machine" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        state.boot_time = machine::read(tip, &state.roots);
+    }
+}
+
+struct Filesystems(filesystems::ReadCache);
+
+impl Collector for Filesystems {
+    fn name(&self) -> &'static str { "filesystems" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        filesystems::read(tip, &self.0, &state.roots);
+    }
+}
+
+struct Interfaces(interfaces::ReadCache);
+
+impl Collector for Interfaces {
+    fn name(&self) -> &'static str { "interfaces" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        interfaces::read(tip, &mut self.0, &state.roots);
+    }
+}
+
+struct Sensors;
+
+impl Collector for Sensors {
+    fn name(&self) -> &'static str { "sensors" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        sensors::read(tip, &state.roots);
+    }
+}
+
+struct CGroupStats;
+
+impl Collector for CGroupStats {
+    fn name(&self) -> &'static str { "cgroup_stats" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        cgroup_stats::read(tip, &state.roots);
+    }
+}
+
+/// Reads cgroups of processes too, as they are only used for processes,
+/// and environment, as process groups depend on it
+struct Processes {
+    cache: processes::ReadCache,
+    environ: values::EnvironCache,
+    grouping: groups::Grouping,
+    groups: groups::ReadCache,
+    by_cgroup: groups::Grouping,
+    cgroup_groups: groups::ReadCache,
+}
+
+impl Collector for Processes {
+    fn name(&self) -> &'static str { "processes" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        state.cgroups = cgroups::read(&state.roots);
+        let prcs = processes::read(&mut self.cache,
+                                   &state.cgroups, &state.roots);
+        state.processes = Arc::new(prcs);
+        state.environ = values::read_environ(&mut self.environ,
+                                             &state.processes, &state.roots);
+        let now = time_ms();
+        let ungrouped = groups::write_tip(tip, &mut self.groups,
+            &self.grouping, &state.processes, &state.environ,
+            &self.cache.unreadable, now);
+        if self.grouping.per_pid {
+            processes::write_tip(tip, state.processes.iter(),
+                                 &state.cgroups);
+        } else {
+            processes::write_tip(tip, ungrouped, &state.cgroups);
+        }
+        groups::write_tip(tip, &mut self.cgroup_groups, &self.by_cgroup,
+            &state.processes, &state.environ, &self.cache.unreadable, now);
+    }
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("processes_read", self.cache.processes_read),
+            ("cmdline_reads", self.cache.cmdline_reads),
+            ("environ_reads", self.environ.environ_reads),
+            ("errors", self.cache.errors),
+        ]
+    }
+}
+
+struct Sockets(connections::ReadCache);
+
+impl Collector for Sockets {
+    fn name(&self) -> &'static str { "connections" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        let conn = connections::read(&mut self.0,
+            &state.processes, &state.cgroups, &state.roots);
+        if let Some(ref conn) = conn {
+            connections::write_tip(tip, conn);
+        }
+        state.connections = conn;
+    }
+}
+
+struct Values {
+    cache: values::ReadCache,
+    configs: Vec<values::Config>,
+}
+
+impl Collector for Values {
+    fn name(&self) -> &'static str { "values" }
+    fn collect(&mut self, tip: &mut Tip, state: &mut State) {
+        values::read(tip, &mut self.cache, &state.processes, &state.environ,
+                     &state.cgroups, &state.roots);
+        values::read_static(tip, &mut self.cache, &self.configs);
+    }
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("files_read", self.cache.files_read),
+            ("metadata_reads", self.cache.metadata_reads),
+            ("errors", self.cache.errors),
+        ]
+    }
+}
+
+struct Statsd(Arc<Mutex<statsd::Aggregator>>);
+
+impl Collector for Statsd {
+    fn name(&self) -> &'static str { "statsd" }
+    fn collect(&mut self, tip: &mut Tip, _state: &mut State) {
+        self.0.lock().unwrap().write_tip(tip, time_ms());
+    }
+    fn counters(&self) -> Vec<(&'static str, u64)> {
+        let aggr = self.0.lock().unwrap();
+        vec![
+            ("dropped", aggr.dropped),
+            ("expired", aggr.expired),
+        ]
+    }
+}
+
+struct Prometheus(Arc<prometheus::Targets>);
+
+impl Collector for Prometheus {
+    fn name(&self) -> &'static str { "prometheus" }
+    fn collect(&mut self, tip: &mut Tip, _state: &mut State) {
+        self.0.write_tip(tip);
+    }
+}
+
+struct Scripts(Arc<scripts::Scripts>);
+
+impl Collector for Scripts {
+    fn name(&self) -> &'static str { "scripts" }
+    fn collect(&mut self, tip: &mut Tip, _state: &mut State) {
+        self.0.write_tip(tip);
+    }
+}
+
+/// Builds the registry of all built-in collectors
+///
+/// New collectors should be added here, in the order of dependencies
+pub fn standard(deps: &Dependencies, scan_interval: u32, scan_threads: usize,
+    configs: &Configs)
+    -> Registry
+{
+    let mut reg = Registry::new(scan_interval, &configs.collectors);
+    reg.add(Machine);
+    reg.add(Filesystems(filesystems::ReadCache::new()));
+    reg.add(Interfaces(interfaces::ReadCache::new()));
+    reg.add(Sensors);
+    reg.add(CGroupStats);
+    let mut processes_cache = processes::ReadCache::new();
+    processes_cache.threads = scan_threads;
+    reg.add(Processes {
+        cache: processes_cache,
+        environ: values::EnvironCache::new(),
+        grouping: groups::Grouping::new(&configs.groups),
+        groups: groups::ReadCache::new(),
+        by_cgroup: groups::Grouping::cgroups(),
+        cgroup_groups: groups::ReadCache::new(),
+    });
+    reg.add(Sockets(connections::ReadCache::new()));
+    reg.add(Values {
+        cache: values::ReadCache::new(),
+        configs: configs.values.clone(),
+    });
+    if let Some(aggr) = deps.get::<Arc<Mutex<statsd::Aggregator>>>() {
+        reg.add(Statsd(aggr.clone()));
+    }
+    if let Some(targets) = deps.get::<Arc<prometheus::Targets>>() {
+        reg.add(Prometheus(targets.clone()));
+    }
+    if let Some(scripts) = deps.get::<Arc<scripts::Scripts>>() {
+        reg.add(Scripts(scripts.clone()));
+    }
+    reg.check_unused();
+    return reg;
+}
+
+impl State {
+    pub fn new(roots: Roots) -> State {
+        State {
+            roots: roots,
+            boot_time: None,
+            cgroups: HashMap::new(),
+            processes: Arc::new(Vec::new()),
+            environ: Arc::new(HashMap::new()),
+            connections: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread::sleep;
+    use std::time::Duration;
+    use std::collections::HashMap;
+
+    use cantal::Value;
+    use history::Key;
+    use scan::{Tip, Roots};
+    use scan::time_ms;
+    use super::{Registry, Collector, State, Config, TIMEOUT_BACKOFF};
+
+    struct Count(u64);
+
+    impl Collector for Count {
+        fn name(&self) -> &'static str { "count" }
+        fn collect(&mut self, tip: &mut Tip, _state: &mut State) {
+            self.0 += 1;
+            tip.add(Key::metric("count"), Value::Counter(self.0));
+            tip.add(Key::metric("level"), Value::Integer(self.0 as i64));
+        }
+    }
+
+    /// Takes 500 ms on the first run
+    struct Slow(u64);
+
+    impl Collector for Slow {
+        fn name(&self) -> &'static str { "count" }
+        fn collect(&mut self, tip: &mut Tip, _state: &mut State) {
+            if self.0 == 0 {
+                sleep(Duration::from_millis(500));
+            }
+            self.0 += 1;
+            tip.add(Key::metric("count"), Value::Counter(self.0));
+        }
+    }
+
+    fn run(reg: &mut Registry, now: u64) -> Option<u64> {
+        let mut tip = Tip::new();
+        reg.collect(&mut tip, &mut State::new(Roots::new()), now);
+        match tip.map.get(&Key::metric("count")) {
+            Some(&Value::Counter(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn every_scan() {
+        let mut reg = Registry::new(2000, &HashMap::new());
+        reg.add(Count(0));
+        assert_eq!(run(&mut reg, 2000), Some(1));
+        assert_eq!(run(&mut reg, 4000), Some(2));
+    }
+
+    #[test]
+    fn interval() {
+        let mut configs = HashMap::new();
+        configs.insert("count".to_string(), Config {
+            enabled: true,
+            interval: Some(10000),
+            timeout: None,
+        });
+        let mut reg = Registry::new(2000, &configs);
+        reg.add(Count(0));
+        assert_eq!(run(&mut reg, 10000), Some(1));
+        // counters have a gap while collector is skipped
+        assert_eq!(run(&mut reg, 12001), None);
+        assert_eq!(run(&mut reg, 18002), None);
+        assert_eq!(run(&mut reg, 19999), Some(2));
+
+        // but levels are repeated
+        let mut tip = Tip::new();
+        let mut state = State::new(Roots::new());
+        reg.collect(&mut tip, &mut state, 22000);
+        assert!(matches!(tip.map.get(&Key::metric("level")),
+                         Some(&Value::Integer(2))));
+    }
+
+    #[test]
+    fn self_metrics() {
+        let mut reg = Registry::new(2000, &HashMap::new());
+        reg.add(Count(0));
+        let mut tip = Tip::new();
+        let mut state = State::new(Roots::new());
+        reg.collect(&mut tip, &mut state, 2000);
+        reg.collect(&mut tip, &mut state, 4000);
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                ("collector", "count"),
+                ("metric", "cantal.collector.runs"),
+            ])), Some(&Value::Counter(2))));
+        assert!(tip.map.contains_key(&Key::pairs(&[
+                ("collector", "count"),
+                ("metric", "cantal.collector.duration"),
+            ])));
+        let stats = reg.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].name, "count");
+        assert_eq!(stats[0].runs, 2);
+        assert_eq!(stats[0].last_run, 4000);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut configs = HashMap::new();
+        configs.insert("count".to_string(), Config {
+            enabled: false,
+            interval: None,
+            timeout: None,
+        });
+        let mut reg = Registry::new(2000, &configs);
+        reg.add(Count(0));
+        assert_eq!(run(&mut reg, 2000), None);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut configs = HashMap::new();
+        configs.insert("count".to_string(), Config {
+            enabled: true,
+            interval: None,
+            timeout: Some(50),
+        });
+        let mut reg = Registry::new(2000, &configs);
+        reg.add(Slow(0));
+        let start = time_ms();
+        assert_eq!(run(&mut reg, 2000), None);
+        assert!(time_ms() - start < 500);
+        // postponed, and then it's still running
+        assert_eq!(run(&mut reg, 4000), None);
+        assert_eq!(run(&mut reg, 2000 + 2000*TIMEOUT_BACKOFF), None);
+        sleep(Duration::from_millis(600));
+        // results of the first run are dropped
+        assert_eq!(run(&mut reg, 4000 + 2000*TIMEOUT_BACKOFF), Some(2));
+    }
+}
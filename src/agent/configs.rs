@@ -1,21 +1,42 @@
 use std::path::Path;
 use std::default::Default;
+use std::collections::HashMap;
 
 use scan_dir::ScanDir;
 use carbon::{Config as Carbon, validator as carbon_validator};
+use scan::values::{Config as Values, validator as values_validator};
+use prometheus::{Config as Prometheus, validator as prometheus_validator};
+use collector::{Config as Collector, validator as collector_validator};
+use scripts::{Config as Script, validator as script_validator};
+use scan::groups::{Config as Groups, validator as groups_validator};
 use quire::parse_config;
 
 
 #[derive(Clone)]
 pub struct Configs {
    pub carbon: Vec<Carbon>,
+   pub values: Vec<Values>,
+   pub prometheus: Vec<Prometheus>,
+   pub collectors: HashMap<String, Collector>,
+   pub scripts: Vec<Script>,
+   pub groups: Vec<Groups>,
 }
 
 pub fn read(dir: &Path) -> Configs {
     let mut configs = Configs {
         carbon: Vec::new(),
+        values: Vec::new(),
+        prometheus: Vec::new(),
+        collectors: HashMap::new(),
+        scripts: Vec::new(),
+        groups: Vec::new(),
     };
     let carbon = carbon_validator();
+    let values = values_validator();
+    let prometheus = prometheus_validator();
+    let collectors = collector_validator();
+    let scripts = script_validator();
+    let groups = groups_validator();
     let quire = Default::default();
     ScanDir::files().read(dir, |iter| {
         for (entry, name) in iter {
@@ -28,6 +49,60 @@ pub fn read(dir: &Path) -> Configs {
                     }
                 };
                 configs.carbon.push(cfg);
+            } else if name.ends_with(".metrics.yaml") {
+                let cfg = match parse_config(entry.path(), &values, quire) {
+                    Ok(cfg) => cfg,
+                    Err(e) => {
+                        warn!("Error reading config {:?}", e);
+                        continue;
+                    }
+                };
+                configs.values.push(cfg);
+            } else if name.ends_with(".prometheus.yaml") {
+                let cfg = match parse_config(entry.path(), &prometheus, quire)
+                {
+                    Ok(cfg) => cfg,
+                    Err(e) => {
+                        warn!("Error reading config {:?}", e);
+                        continue;
+                    }
+                };
+                configs.prometheus.push(cfg);
+            } else if name.ends_with(".collectors.yaml") {
+                let cfg: HashMap<String, Collector> = match
+                    parse_config(entry.path(), &collectors, quire)
+                {
+                    Ok(cfg) => cfg,
+                    Err(e) => {
+                        warn!("Error reading config {:?}", e);
+                        continue;
+                    }
+                };
+                configs.collectors.extend(cfg.into_iter());
+            } else if name.ends_with(".script.yaml") {
+                let mut cfg: Script = match
+                    parse_config(entry.path(), &scripts, quire)
+                {
+                    Ok(cfg) => cfg,
+                    Err(e) => {
+                        warn!("Error reading config {:?}", e);
+                        continue;
+                    }
+                };
+                if cfg.name.is_none() {
+                    let stem = &name[..name.len() - ".script.yaml".len()];
+                    cfg.name = Some(stem.to_string());
+                }
+                configs.scripts.push(cfg);
+            } else if name.ends_with(".processes.yaml") {
+                let cfg = match parse_config(entry.path(), &groups, quire) {
+                    Ok(cfg) => cfg,
+                    Err(e) => {
+                        warn!("Error reading config {:?}", e);
+                        continue;
+                    }
+                };
+                configs.groups.push(cfg);
             } else {
                 warn!("Unknown configuration file {:?}", entry.path());
             }
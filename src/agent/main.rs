@@ -1,3 +1,5 @@
+#![cfg_attr(feature="nightly", feature(test))]
+
 extern crate libc;
 #[macro_use] extern crate log;
 extern crate cbor;
@@ -24,6 +26,7 @@ extern crate scan_dir;
 #[macro_use] extern crate rotor;
 extern crate rotor_carbon;
 extern crate rotor_tools;
+#[cfg(all(test, feature="nightly"))] extern crate test;
 
 extern crate cantal_values as cantal;
 extern crate cantal_history as history;
@@ -70,6 +73,10 @@ mod info;
 mod rotorloop;
 mod carbon;
 mod configs;
+mod statsd;
+mod prometheus;
+mod collector;
+mod scripts;
 
 
 fn main() {
@@ -92,6 +99,11 @@ fn run() -> Result<(), Box<Error>> {
     let mut machine_id = None::<String>;
     let mut cluster_name = None::<String>;
     let mut scan_interval = None::<u32>;
+    let mut scan_threads = 1usize;
+    let mut statsd_addr = None::<SocketAddr>;
+    let mut proc_root = None::<PathBuf>;
+    let mut sys_root = None::<PathBuf>;
+    let mut cgroup_root = None::<PathBuf>;
     let mut log_level = env::var("RUST_LOG").ok()
         .and_then(|x| FromStr::from_str(&x).ok());
     {
@@ -115,6 +127,11 @@ fn run() -> Result<(), Box<Error>> {
         ap.refer(&mut scan_interval)
             .add_option(&["-i", "--interval"], StoreOption,
             "Scan interval in milliseconds (default 2000 ms)");
+        ap.refer(&mut scan_threads)
+            .add_option(&["--scan-threads"], Store, "
+                Number of threads to read processes in (default 1). Only
+                useful on machines with many thousands of processes.
+            ");
         ap.refer(&mut cluster_name)
             .add_option(&["-n", "--cluster-name"], StoreOption, "
                 A name of the cluster. If cantal receives ping packet with
@@ -136,6 +153,23 @@ fn run() -> Result<(), Box<Error>> {
         ap.refer(&mut log_level)
             .add_option(&["--log-level"], StoreOption,
                 "Log level");
+        ap.refer(&mut statsd_addr)
+            .add_option(&["--statsd"], StoreOption, "
+                Listen for StatsD metrics at the specified UDP address
+                (e.g. `127.0.0.1:8125`). Disabled by default.
+            ");
+        ap.refer(&mut proc_root)
+            .add_option(&["--proc-root"], ParseOption, "
+                Where proc filesystem is mounted (default `/proc`). Useful
+                when running in a container with host's proc mounted
+                elsewhere, e.g. `/host/proc`
+            ");
+        ap.refer(&mut sys_root)
+            .add_option(&["--sys-root"], ParseOption,
+                "Where sysfs is mounted (default `/sys`)");
+        ap.refer(&mut cgroup_root)
+            .add_option(&["--cgroup-root"], ParseOption,
+                "Where cgroups are mounted (default `<sys-root>/fs/cgroup`)");
         ap.parse_args_or_exit();
     }
 
@@ -196,7 +230,7 @@ fn run() -> Result<(), Box<Error>> {
                 let v: history::VersionInfo = try!(probor::decode(&mut dec)
                     .map_err(|_| error!("Can't decode version info. \
                         Ignoring...")));
-                if v != history::VersionInfo::current() {
+                if !v.is_supported() {
                     error!("Old version of history data. Ignoring...");
                     return Err(());
                 }
@@ -233,9 +267,29 @@ fn run() -> Result<(), Box<Error>> {
         .ok();
     }
 
+    if let Some(ref addr) = statsd_addr {
+        try!(statsd::start(&mut deps, addr));
+    }
+    prometheus::start(&mut deps, &configs.prometheus);
+    scripts::start(&mut deps, &configs.scripts);
+
+    let mut roots = scan::Roots::new();
+    if let Some(dir) = sys_root {
+        roots.cgroup_dir = dir.join("fs/cgroup");
+        roots.sys_dir = dir;
+    }
+    if let Some(dir) = cgroup_root {
+        roots.cgroup_dir = dir;
+    }
+    if let Some(dir) = proc_root {
+        roots.proc_dir = dir;
+    }
+
     let mydeps = deps.clone();
+    let scan_configs = configs.clone();
     let _scan = thread::spawn(move || {
-        scanner::scan_loop(mydeps, scan_interval.unwrap_or(2000));
+        scanner::scan_loop(mydeps, scan_interval.unwrap_or(2000),
+                           scan_threads, scan_configs, roots);
     });
 
     let mydeps = deps.clone();
@@ -0,0 +1,352 @@
+use std::thread;
+use std::io::Read;
+use std::error::Error;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, BTreeMap};
+
+use hyper::Client;
+use hyper::status::StatusCode;
+use quire::validate::{Structure, Scalar, Numeric, Mapping};
+
+use cantal::Value;
+use history::Key;
+use scan::{Tip, time_ms};
+use deps::Dependencies;
+
+
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Config {
+    pub url: String,
+    pub interval: u32,
+    pub timeout: u32,
+    pub labels: BTreeMap<String, String>,
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("url", Scalar::new())
+    .member("interval",
+        Numeric::new().min(1).max(86400).default(10))
+    .member("timeout",
+        Numeric::new().min(1).max(86400).default(5))
+    .member("labels", Mapping::new(Scalar::new(), Scalar::new()))
+}
+
+/// Last scraped values of every target, in the order of configs
+pub struct Targets(Vec<Arc<Mutex<Vec<(Key, Value)>>>>);
+
+impl Targets {
+    pub fn write_tip(&self, tip: &mut Tip) {
+        for target in self.0.iter() {
+            for &(ref key, ref value) in target.lock().unwrap().iter() {
+                tip.add(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Parses name and labels of a sample and returns the rest of the line
+fn parse_sample(line: &str)
+    -> Option<(&str, Vec<(String, String)>, &str)>
+{
+    let name_end = line.find(|c: char| c == '{' || c.is_whitespace())
+        .unwrap_or(line.len());
+    let name = &line[..name_end];
+    let mut labels = Vec::new();
+    let mut rest = &line[name_end..];
+    if rest.starts_with("{") {
+        rest = &rest[1..];
+        loop {
+            rest = rest.trim_left_matches(|c: char| c == ',' || c == ' ');
+            if rest.starts_with("}") {
+                rest = &rest[1..];
+                break;
+            }
+            let eq = match rest.find('=') {
+                Some(x) => x,
+                None => return None,
+            };
+            let lname = rest[..eq].trim();
+            rest = &rest[eq+1..];
+            if !rest.starts_with("\"") {
+                return None;
+            }
+            let mut value = String::new();
+            let mut end = None;
+            let mut chars = rest.char_indices().skip(1);
+            while let Some((idx, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = Some(idx);
+                        break;
+                    }
+                    '\\' => match chars.next() {
+                        Some((_, 'n')) => value.push('\n'),
+                        Some((_, c)) => value.push(c),
+                        None => return None,
+                    },
+                    c => value.push(c),
+                }
+            }
+            match end {
+                Some(idx) => rest = &rest[idx+1..],
+                None => return None,
+            }
+            labels.push((lname.to_string(), value));
+        }
+    }
+    if name.len() == 0 {
+        return None;
+    }
+    Some((name, labels, rest))
+}
+
+/// Type of the metric, histograms and summaries expose several series
+/// with a suffix appended to the name of the metric
+fn metric_type<'x>(types: &'x HashMap<String, String>, name: &str)
+    -> Option<&'x str>
+{
+    if let Some(typ) = types.get(name) {
+        return Some(&typ[..]);
+    }
+    for &suffix in &["_bucket", "_sum", "_count"] {
+        if name.ends_with(suffix) {
+            let base = &name[..name.len() - suffix.len()];
+            if let Some(typ) = types.get(base) {
+                return Some(&typ[..]);
+            }
+        }
+    }
+    return None;
+}
+
+/// Parses prometheus text exposition format
+///
+/// Only counters, gauges and untyped metrics are returned. Labels of the
+/// sample are used as a key, `extra` labels override them. The `url` of
+/// the target and the `metric` name are always added, sample labels of the
+/// same name are renamed to `exported_url` and `exported_metric`, like
+/// prometheus itself does.
+pub fn parse(text: &str, url: &str, extra: &BTreeMap<String, String>)
+    -> Vec<(Key, Value)>
+{
+    let mut types = HashMap::new();
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.len() == 0 {
+            continue;
+        }
+        if line.starts_with("#") {
+            let mut words = line[1..].split_whitespace();
+            if words.next() == Some("TYPE") {
+                if let (Some(name), Some(typ)) = (words.next(), words.next())
+                {
+                    types.insert(name.to_string(), typ.to_string());
+                }
+            }
+            continue;
+        }
+        let (name, labels, rest) = match parse_sample(line) {
+            Some(x) => x,
+            None => {
+                debug!("Bad prometheus line {:?}", line);
+                continue;
+            }
+        };
+        // timestamp, if any, is ignored
+        let value: f64 = match rest.split_whitespace().next()
+            .and_then(|x| x.parse().ok())
+        {
+            Some(x) if x.is_finite() => x,
+            _ => continue,
+        };
+        // Counters are often fractional, e.g. `process_cpu_seconds_total`,
+        // and the type of the value must not change between scrapes, so
+        // all of them are floats, the same as in prometheus itself
+        let value = match metric_type(&types, name) {
+            Some("counter") if value >= 0. => Value::Float(value),
+            Some("gauge") | Some("untyped") | None => Value::Float(value),
+            _ => continue,
+        };
+        let mut pairs = labels.into_iter()
+            .map(|(k, v)| match &k[..] {
+                "metric" | "url" => (format!("exported_{}", k), v),
+                _ => (k, v),
+            })
+            .collect::<BTreeMap<_, _>>();
+        for (k, v) in extra.iter() {
+            pairs.insert(k.clone(), v.clone());
+        }
+        pairs.insert("url".to_string(), url.to_string());
+        pairs.insert("metric".to_string(), name.to_string());
+        let pairs = pairs.iter().map(|(k, v)| (&k[..], &v[..]))
+            .collect::<Vec<_>>();
+        result.push((Key::pairs(&pairs), value));
+    }
+    return result;
+}
+
+fn scrape(client: &Client, cfg: &Config)
+    -> Result<Vec<(Key, Value)>, Box<Error>>
+{
+    let mut response = try!(client.get(&cfg.url[..]).send());
+    if response.status != StatusCode::Ok {
+        return Err(From::from(format!("Bad status {}", response.status)));
+    }
+    let mut body = String::new();
+    try!(response.read_to_string(&mut body));
+    Ok(parse(&body, &cfg.url, &cfg.labels))
+}
+
+fn scrape_loop(cfg: Config, target: Arc<Mutex<Vec<(Key, Value)>>>) {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(Duration::from_secs(cfg.timeout as u64)));
+    client.set_write_timeout(Some(Duration::from_secs(cfg.timeout as u64)));
+    let interval = cfg.interval as u64 * 1000;
+    loop {
+        match scrape(&client, &cfg) {
+            Ok(values) => {
+                *target.lock().unwrap() = values;
+            }
+            Err(e) => {
+                info!("Error scraping {:?}: {}", cfg.url, e);
+                // stale values are worse than absent ones
+                target.lock().unwrap().clear();
+            }
+        }
+        thread::sleep(Duration::from_millis(
+            interval - time_ms() % interval));
+    }
+}
+
+/// Starts a thread per target and puts `Targets` into dependencies
+pub fn start(deps: &mut Dependencies, configs: &[Config]) {
+    if configs.len() == 0 {
+        return;
+    }
+    let mut targets = Vec::new();
+    for cfg in configs {
+        let target = Arc::new(Mutex::new(Vec::new()));
+        targets.push(target.clone());
+        let cfg = cfg.clone();
+        thread::spawn(move || {
+            scrape_loop(cfg, target);
+        });
+    }
+    deps.insert(Arc::new(Targets(targets)));
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::collections::BTreeMap;
+
+    use hyper::Client;
+    use cantal::Value;
+    use history::Key;
+    use super::{parse, scrape, Config};
+
+    const EXAMPLE: &'static str = "\
+        # HELP http_requests_total The total number of HTTP requests.\n\
+        # TYPE http_requests_total counter\n\
+        http_requests_total{method=\"post\",code=\"200\"} 1027 1395066363000\n\
+        http_requests_total{method=\"post\",code=\"400\"}    3\n\
+        # TYPE process_cpu_seconds_total counter\n\
+        process_cpu_seconds_total 12.47\n\
+        # TYPE temperature gauge\n\
+        temperature{room=\"a \\\"b\\\"\"} -1.5\n\
+        # TYPE latency histogram\n\
+        latency_bucket{le=\"0.5\"} 10\n\
+        latency_count 10\n\
+        queue_length 7\n\
+        renamed{metric=\"x\",url=\"/\"} 2\n\
+        broken{x=} 1\n\
+    ";
+
+    fn get<'x>(values: &'x [(Key, Value)], key: &Key) -> Option<&'x Value> {
+        values.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v)
+    }
+
+    #[test]
+    fn parse_example() {
+        let url = "http://127.0.0.1:9100/metrics";
+        let mut extra = BTreeMap::new();
+        extra.insert("job".to_string(), "web".to_string());
+        let values = parse(EXAMPLE, url, &extra);
+        assert_eq!(values.len(), 6);
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("code", "200"),
+                ("job", "web"),
+                ("method", "post"),
+                ("metric", "http_requests_total"),
+                ("url", url),
+            ])), Some(&Value::Float(x)) if x == 1027.));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("code", "400"),
+                ("job", "web"),
+                ("method", "post"),
+                ("metric", "http_requests_total"),
+                ("url", url),
+            ])), Some(&Value::Float(x)) if x == 3.));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("job", "web"),
+                ("metric", "process_cpu_seconds_total"),
+                ("url", url),
+            ])), Some(&Value::Float(x)) if x == 12.47));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("job", "web"),
+                ("metric", "temperature"),
+                ("room", "a \"b\""),
+                ("url", url),
+            ])), Some(&Value::Float(x)) if x == -1.5));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("job", "web"),
+                ("metric", "queue_length"),
+                ("url", url),
+            ])), Some(&Value::Float(x)) if x == 7.));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("exported_metric", "x"),
+                ("exported_url", "/"),
+                ("job", "web"),
+                ("metric", "renamed"),
+                ("url", url),
+            ])), Some(&Value::Float(x)) if x == 2.));
+    }
+
+    #[test]
+    fn scrape_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let bytes = sock.read(&mut buf).unwrap();
+                assert!(bytes > 0);
+                request.extend(&buf[..bytes]);
+            }
+            write!(sock, "HTTP/1.1 200 OK\r\n\
+                          Content-Type: text/plain; version=0.0.4\r\n\
+                          Content-Length: {}\r\n\
+                          Connection: close\r\n\r\n{}",
+                          EXAMPLE.len(), EXAMPLE).unwrap();
+        });
+        let cfg = Config {
+            url: format!("http://{}/metrics", addr),
+            interval: 10,
+            timeout: 5,
+            labels: BTreeMap::new(),
+        };
+        let values = scrape(&Client::new(), &cfg).unwrap();
+        assert_eq!(values.len(), 6);
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "queue_length"),
+                ("url", &cfg.url[..]),
+            ])), Some(&Value::Float(x)) if x == 7.));
+    }
+}
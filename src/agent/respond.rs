@@ -14,6 +14,7 @@ use super::storage::{StorageStats};
 use super::http::{Request, BadRequest};
 use super::server::Context;
 use super::stats::Stats;
+use super::collector::CollectorStats;
 use super::p2p::GossipStats;
 use super::remote::{Peers};
 use super::deps::LockedDeps;
@@ -21,11 +22,12 @@ use super::websock::Beacon;
 
 
 #[derive(RustcEncodable)]
-struct StatusData {
+struct StatusData<'a> {
     pub startup_time: u64,
     pub scan_duration: u32,
     pub storage: StorageStats,
     pub boot_time: Option<u64>,
+    pub collectors: &'a Vec<CollectorStats>,
 }
 
 #[derive(RustcEncodable)]
@@ -43,6 +45,7 @@ pub fn serve_status(_req: &Request, context: &mut Context)
             scan_duration: stats.scan_duration,
             storage: stats.storage,
             boot_time: stats.boot_time,
+            collectors: &stats.collectors,
         }))
 }
 
@@ -0,0 +1,357 @@
+//! Resource usage of cgroups as accounted by the kernel
+//!
+//! Unlike sums of per-process values, these include exited children,
+//! page cache, and throttling. Metrics of v1 controllers are renamed to
+//! their v2 counterparts, so both kinds of hosts have the same metrics.
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use cantal::Value::{Integer, Counter};
+use history::Key;
+use super::{Tip, Roots};
+use super::mountinfo;
+use super::cgroups::walk;
+
+
+enum Kind {
+    Integer,
+    Counter,
+    /// Counter which kernel reports in nanoseconds, stored in microseconds
+    Nanoseconds,
+}
+
+enum Hierarchy {
+    /// All controllers are in the same tree
+    Unified(PathBuf),
+    /// Mount points of the v1 controllers we read from
+    Legacy {
+        memory: Option<PathBuf>,
+        cpu: Option<PathBuf>,
+        cpuacct: Option<PathBuf>,
+        blkio: Option<PathBuf>,
+        pids: Option<PathBuf>,
+    },
+}
+
+const MEMORY_STAT: &'static [(&'static str, &'static str, Kind)] = &[
+    ("anon", "memory.anon", Kind::Integer),
+    ("file", "memory.file", Kind::Integer),
+    ("kernel_stack", "memory.kernel_stack", Kind::Integer),
+    ("sock", "memory.sock", Kind::Integer),
+    ("shmem", "memory.shmem", Kind::Integer),
+    ("file_dirty", "memory.dirty", Kind::Integer),
+    ("file_writeback", "memory.writeback", Kind::Integer),
+    ("pgfault", "memory.pgfault", Kind::Counter),
+    ("pgmajfault", "memory.pgmajfault", Kind::Counter),
+];
+
+const MEMORY_STAT_V1: &'static [(&'static str, &'static str, Kind)] = &[
+    ("rss", "memory.anon", Kind::Integer),
+    ("cache", "memory.file", Kind::Integer),
+    ("shmem", "memory.shmem", Kind::Integer),
+    ("dirty", "memory.dirty", Kind::Integer),
+    ("writeback", "memory.writeback", Kind::Integer),
+    ("swap", "memory.swap", Kind::Integer),
+    ("pgfault", "memory.pgfault", Kind::Counter),
+    ("pgmajfault", "memory.pgmajfault", Kind::Counter),
+];
+
+const MEMORY_EVENTS: &'static [(&'static str, &'static str, Kind)] = &[
+    ("high", "memory.events.high", Kind::Counter),
+    ("max", "memory.events.max", Kind::Counter),
+    ("oom", "memory.events.oom", Kind::Counter),
+    ("oom_kill", "memory.events.oom_kill", Kind::Counter),
+];
+
+const OOM_CONTROL_V1: &'static [(&'static str, &'static str, Kind)] = &[
+    ("oom_kill", "memory.events.oom_kill", Kind::Counter),
+];
+
+const CPU_STAT: &'static [(&'static str, &'static str, Kind)] = &[
+    ("usage_usec", "cpu.usage_usec", Kind::Counter),
+    ("user_usec", "cpu.user_usec", Kind::Counter),
+    ("system_usec", "cpu.system_usec", Kind::Counter),
+    ("nr_periods", "cpu.nr_periods", Kind::Counter),
+    ("nr_throttled", "cpu.nr_throttled", Kind::Counter),
+    ("throttled_usec", "cpu.throttled_usec", Kind::Counter),
+];
+
+const CPU_STAT_V1: &'static [(&'static str, &'static str, Kind)] = &[
+    ("nr_periods", "cpu.nr_periods", Kind::Counter),
+    ("nr_throttled", "cpu.nr_throttled", Kind::Counter),
+    ("throttled_time", "cpu.throttled_usec", Kind::Nanoseconds),
+];
+
+fn key(cgroup: &str, metric: &str) -> Key {
+    Key::pairs(&[
+        ("cgroup", cgroup),
+        ("metric", metric),
+    ])
+}
+
+fn add(tip: &mut Tip, cgroup: &str, metric: &str, kind: &Kind, value: u64) {
+    let value = match *kind {
+        Kind::Integer => Integer(value as i64),
+        Kind::Counter => Counter(value),
+        Kind::Nanoseconds => Counter(value / 1000),
+    };
+    tip.add(key(cgroup, metric), value);
+}
+
+fn read_file(path: &Path, buf: &mut String) -> bool {
+    buf.truncate(0);
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(buf))
+        .map_err(|e| debug!("Can't read {:?}: {}", path, e))
+        .is_ok()
+}
+
+/// Reads a file containing a single number
+fn read_number(tip: &mut Tip, cgroup: &str, path: &Path, buf: &mut String,
+    metric: &str, kind: Kind)
+{
+    if read_file(path, buf) {
+        if let Ok(value) = buf.trim().parse() {
+            add(tip, cgroup, metric, &kind, value);
+        }
+    }
+}
+
+/// Reads a file of `name value` lines, like `memory.stat`
+fn read_flat_keyed(tip: &mut Tip, cgroup: &str, path: &Path,
+    buf: &mut String, table: &[(&str, &str, Kind)])
+{
+    if !read_file(path, buf) {
+        return;
+    }
+    for line in buf.lines() {
+        let mut words = line.split_whitespace();
+        if let (Some(name), Some(value)) = (words.next(), words.next()) {
+            for &(field, metric, ref kind) in table {
+                if field == name {
+                    if let Ok(value) = value.parse() {
+                        add(tip, cgroup, metric, kind, value);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Sums `io.stat` over devices
+///
+/// The line is:
+///  8:0 rbytes=90112 wbytes=0 rios=6 wios=0 dbytes=0 dios=0
+fn parse_io_stat(text: &str) -> [u64; 4] {
+    let mut result = [0; 4];
+    for line in text.lines() {
+        for pair in line.split_whitespace().skip(1) {
+            let mut kv = pair.splitn(2, '=');
+            let idx = match kv.next() {
+                Some("rbytes") => 0,
+                Some("wbytes") => 1,
+                Some("rios") => 2,
+                Some("wios") => 3,
+                _ => continue,
+            };
+            result[idx] += kv.next().and_then(|x| x.parse().ok())
+                .unwrap_or(0);
+        }
+    }
+    return result;
+}
+
+/// Sums `blkio.throttle.io_service_bytes` or `io_serviced` over devices
+///
+/// Returns reads and writes. The lines are:
+///  8:0 Read 90112
+///  8:0 Write 0
+fn parse_blkio(text: &str) -> [u64; 2] {
+    let mut result = [0; 2];
+    for line in text.lines() {
+        let mut words = line.split_whitespace().skip(1);
+        let idx = match words.next() {
+            Some("Read") => 0,
+            Some("Write") => 1,
+            _ => continue,
+        };
+        result[idx] += words.next().and_then(|x| x.parse().ok())
+            .unwrap_or(0);
+    }
+    return result;
+}
+
+fn add_io(tip: &mut Tip, cgroup: &str, names: &[&str], values: &[u64]) {
+    for (name, &value) in names.iter().zip(values) {
+        tip.add(key(cgroup, name), Counter(value));
+    }
+}
+
+fn find_hierarchy(roots: &Roots) -> Option<Hierarchy> {
+    let mounts = mountinfo::read(roots);
+    let legacy = |name: &str| mounts.iter()
+        .find(|m| m.fstype == "cgroup" &&
+                  m.super_options.iter().any(|x| x == name))
+        .map(|m| roots.cgroup_mount(&m.mount_point));
+    let memory = legacy("memory");
+    let cpu = legacy("cpu");
+    let cpuacct = legacy("cpuacct");
+    let blkio = legacy("blkio");
+    let pids = legacy("pids");
+    // In hybrid mode cgroup2 is also mounted but has no controllers
+    if memory.is_some() || cpu.is_some() || cpuacct.is_some() ||
+        blkio.is_some() || pids.is_some()
+    {
+        return Some(Hierarchy::Legacy {
+            memory: memory,
+            cpu: cpu,
+            cpuacct: cpuacct,
+            blkio: blkio,
+            pids: pids,
+        });
+    }
+    mounts.iter().find(|m| m.fstype == "cgroup2")
+        .map(|m| Hierarchy::Unified(roots.cgroup_mount(&m.mount_point)))
+}
+
+fn read_unified(tip: &mut Tip, root: &Path) {
+    let mut buf = String::with_capacity(4096);
+    walk(root, |dir, name| {
+        read_number(tip, &name, &dir.join("memory.current"), &mut buf,
+            "memory.current", Kind::Integer);
+        read_number(tip, &name, &dir.join("memory.swap.current"), &mut buf,
+            "memory.swap", Kind::Integer);
+        read_flat_keyed(tip, &name, &dir.join("memory.stat"), &mut buf,
+            MEMORY_STAT);
+        read_flat_keyed(tip, &name, &dir.join("memory.events"), &mut buf,
+            MEMORY_EVENTS);
+        read_flat_keyed(tip, &name, &dir.join("cpu.stat"), &mut buf,
+            CPU_STAT);
+        if read_file(&dir.join("io.stat"), &mut buf) {
+            add_io(tip, &name, &["io.rbytes", "io.wbytes", "io.rios",
+                                 "io.wios"], &parse_io_stat(&buf));
+        }
+        read_number(tip, &name, &dir.join("pids.current"), &mut buf,
+            "pids.current", Kind::Integer);
+    });
+}
+
+fn read_legacy(tip: &mut Tip, memory: Option<&PathBuf>,
+    cpu: Option<&PathBuf>, cpuacct: Option<&PathBuf>,
+    blkio: Option<&PathBuf>, pids: Option<&PathBuf>)
+{
+    let mut buf = String::with_capacity(4096);
+    if let Some(root) = memory {
+        walk(root, |dir, name| {
+            read_number(tip, &name, &dir.join("memory.usage_in_bytes"),
+                &mut buf, "memory.current", Kind::Integer);
+            read_number(tip, &name, &dir.join("memory.failcnt"),
+                &mut buf, "memory.events.max", Kind::Counter);
+            read_flat_keyed(tip, &name, &dir.join("memory.stat"), &mut buf,
+                MEMORY_STAT_V1);
+            // Only kernels 4.13+ report `oom_kill` here
+            read_flat_keyed(tip, &name, &dir.join("memory.oom_control"),
+                &mut buf, OOM_CONTROL_V1);
+        });
+    }
+    if let Some(root) = cpu {
+        walk(root, |dir, name| {
+            read_flat_keyed(tip, &name, &dir.join("cpu.stat"), &mut buf,
+                CPU_STAT_V1);
+        });
+    }
+    if let Some(root) = cpuacct {
+        walk(root, |dir, name| {
+            read_number(tip, &name, &dir.join("cpuacct.usage"), &mut buf,
+                "cpu.usage_usec", Kind::Nanoseconds);
+        });
+    }
+    if let Some(root) = blkio {
+        walk(root, |dir, name| {
+            if read_file(&dir.join("blkio.throttle.io_service_bytes"),
+                         &mut buf)
+            {
+                add_io(tip, &name, &["io.rbytes", "io.wbytes"],
+                       &parse_blkio(&buf));
+            }
+            if read_file(&dir.join("blkio.throttle.io_serviced"), &mut buf)
+            {
+                add_io(tip, &name, &["io.rios", "io.wios"],
+                       &parse_blkio(&buf));
+            }
+        });
+    }
+    if let Some(root) = pids {
+        walk(root, |dir, name| {
+            read_number(tip, &name, &dir.join("pids.current"), &mut buf,
+                "pids.current", Kind::Integer);
+        });
+    }
+}
+
+/// Puts kernel-accounted metrics of every cgroup into the tip
+///
+/// Keys are `cgroup` and `metric`, the cgroup names are the same as the
+/// ones used for processes.
+pub fn read(tip: &mut Tip, roots: &Roots) {
+    // TODO(tailhook) should this be cached?
+    match find_hierarchy(roots) {
+        Some(Hierarchy::Unified(ref root)) => read_unified(tip, root),
+        Some(Hierarchy::Legacy { ref memory, ref cpu, ref cpuacct,
+                                 ref blkio, ref pids }) => {
+            read_legacy(tip, memory.as_ref(), cpu.as_ref(), cpuacct.as_ref(),
+                        blkio.as_ref(), pids.as_ref());
+        }
+        None => debug!("No cgroup controllers found"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cantal::Value;
+    use scan::Tip;
+    use scan::test::fixture;
+    use super::{parse_io_stat, parse_blkio, read_unified, key};
+
+    #[test]
+    fn io_stat() {
+        assert_eq!(parse_io_stat("\
+            8:0 rbytes=90112 wbytes=4096 rios=6 wios=1 dbytes=0 dios=0\n\
+            8:16 rbytes=10 wbytes=0 rios=1 wios=0\n\
+            "), [90122, 4096, 7, 1]);
+    }
+
+    #[test]
+    fn blkio() {
+        assert_eq!(parse_blkio("\
+            8:0 Read 90112\n\
+            8:0 Write 4096\n\
+            8:0 Sync 0\n\
+            8:0 Total 94208\n\
+            8:16 Read 10\n\
+            Total 94218\n\
+            "), [90122, 4096]);
+    }
+
+    #[test]
+    fn unified() {
+        let mut tip = Tip::new();
+        read_unified(&mut tip, &fixture().cgroup_dir);
+        assert!(matches!(tip.map.get(&key("system.nscd", "memory.current")),
+                         Some(&Value::Integer(2097152))));
+        assert!(matches!(tip.map.get(&key("system.nscd", "memory.anon")),
+                         Some(&Value::Integer(4096))));
+        assert!(matches!(tip.map.get(&key("system.nscd",
+                                          "memory.pgmajfault")),
+                         Some(&Value::Counter(3))));
+        assert!(matches!(tip.map.get(&key("system.nscd",
+                                          "memory.events.oom_kill")),
+                         Some(&Value::Counter(1))));
+        assert!(matches!(tip.map.get(&key("system.nscd",
+                                          "cpu.throttled_usec")),
+                         Some(&Value::Counter(50))));
+        assert!(tip.map.get(&key("system", "memory.current")).is_none());
+    }
+}
@@ -10,14 +10,35 @@ use std::os::unix::ffi::OsStrExt;
 use scan_dir::ScanDir;
 
 use super::processes::Pid;
+use super::mountinfo;
+use super::Roots;
 
 pub type CGroups = HashMap<Pid, Arc<String>>;
 
 
-fn get_name_dir() -> Option<PathBuf> {
-    let base = Path::new("/sys/fs/cgroup"); // should customize this?
+/// Finds the directory of the hierarchy which is used to name processes
+///
+/// Named (`name=systemd`) v1 hierarchy is preferred, the unified (v2)
+/// hierarchy is used when there is no such one. Directory layout and
+/// `cgroup.procs` files are the same in both, so are the names.
+fn get_name_dir(roots: &Roots) -> Option<PathBuf> {
+    get_named_v1_dir(roots).or_else(|| get_unified_dir(roots))
+}
+
+fn get_unified_dir(roots: &Roots) -> Option<PathBuf> {
+    let dir = mountinfo::read(roots).into_iter()
+        .find(|m| m.fstype == "cgroup2")
+        .map(|m| roots.cgroup_mount(&m.mount_point));
+    if dir.is_none() {
+        debug!("Couldn't find cgroup2 mount");
+    }
+    return dir;
+}
+
+fn get_named_v1_dir(roots: &Roots) -> Option<PathBuf> {
+    let base = &roots.cgroup_dir;
     let mut buf = String::with_capacity(1024);
-    if let Err(_) = File::open("/proc/self/cgroup")
+    if let Err(_) = File::open(roots.proc_path("self/cgroup"))
         .and_then(|mut f| f.read_to_string(&mut buf))
         .map_err(|e| debug!("Can't read cgroup file: {}", e))
     {
@@ -76,47 +97,83 @@ fn make_name(path: &Path, skip_prefix: usize) -> String {
     return buf;
 }
 
-pub fn read() -> CGroups {
+/// Calls `f` with a directory and a name of every interesting cgroup
+///
+/// Works for any hierarchy, including v1 controller ones, as long as the
+/// layout of the directories is the same
+pub fn walk<F>(root: &Path, mut f: F)
+    where F: FnMut(&Path, String)
+{
+    let prefix_num = root.components().count();
+    ScanDir::dirs().walk(root, |mut iter| {
+        while let Some((entry, name)) = iter.next() {
+            if name.ends_with(".swap") || name.ends_with(".mount") {
+                // Systemd stuff, not very interesting here
+                continue;
+            }
+            let path = entry.path();
+            if path.file_stem() == Some(OsStr::from_bytes(b"user")) &&
+                path.components().count() == prefix_num + 1
+            {
+                // Skip "user" cgroup. We don't care about it for
+                // servers, and when writing to graphite it generates
+                // lots of almost random cgroup names so generates
+                // gigabytes of pointless metrics
+                iter.exit_current_dir();
+                continue;
+            }
+            f(&path, make_name(&path, prefix_num));
+        }
+    }).map_err(|e| debug!("Error reading directory {:?}: {:?}", root, e))
+    .ok();
+}
+
+pub fn read(roots: &Roots) -> CGroups {
     let mut pro = HashMap::new();
     let mut buf = String::with_capacity(1024);
     // TODO(tailhook) should this be cached?
-    if let Some(name_dir) = get_name_dir() {
-        let prefix_num = name_dir.components().count();
-        ScanDir::dirs().walk(name_dir, |mut iter| {
-            while let Some((entry, name)) = iter.next() {
-                if name.ends_with(".swap") || name.ends_with(".mount") {
-                    // Systemd stuff, not very interesting here
-                    continue;
-                }
-                let mut path = entry.path();
-                if path.file_stem() == Some(OsStr::from_bytes(b"user")) &&
-                    path.components().count() == prefix_num + 1
-                {
-                    // Skip "user" cgroup. We don't care about it for
-                    // servers, and when writing to graphite it generates
-                    // lots of almost random cgroup names so generates
-                    // gigabytes of pointless metrics
-                    iter.exit_current_dir();
-                    continue;
-                }
-                path.push("cgroup.procs");
-                buf.truncate(0);
-                if File::open(&path)
-                    .and_then(|mut f| f.read_to_string(&mut buf))
-                    .map_err(|e| debug!("Error reading cgroup {:?}: {}",
-                                       path, e))
-                    .is_err()
-                {
-                    continue;
-                }
-                let name = Arc::new(make_name(&path, prefix_num));
-                let pids = buf.split_whitespace()
-                    .filter_map(|x| x.parse().ok());
-                for pid in pids {
-                    pro.insert(pid, name.clone());
-                }
+    if let Some(name_dir) = get_name_dir(roots) {
+        walk(&name_dir, |dir, name| {
+            let path = dir.join("cgroup.procs");
+            buf.truncate(0);
+            if File::open(&path)
+                .and_then(|mut f| f.read_to_string(&mut buf))
+                .map_err(|e| debug!("Error reading cgroup {:?}: {}",
+                                   path, e))
+                .is_err()
+            {
+                return;
+            }
+            let name = Arc::new(name);
+            let pids = buf.split_whitespace()
+                .filter_map(|x| x.parse().ok());
+            for pid in pids {
+                pro.insert(pid, name.clone());
             }
-        }).map_err(|e| debug!("Error reading directory")).ok();
+        });
     }
     return pro;
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::make_name;
+
+    #[test]
+    fn names() {
+        let v1 = Path::new("/sys/fs/cgroup/systemd");
+        let v2 = Path::new("/sys/fs/cgroup");
+        let v1_prefix = v1.components().count();
+        let v2_prefix = v2.components().count();
+        assert_eq!(make_name(
+            &v1.join("system.slice/nscd.service/cgroup.procs"), v1_prefix),
+            "system.nscd");
+        assert_eq!(make_name(
+            &v2.join("system.slice/nscd.service/cgroup.procs"), v2_prefix),
+            "system.nscd");
+        assert_eq!(make_name(
+            &v2.join("machine.slice/app.scope/cgroup.procs"), v2_prefix),
+            "machine.app");
+    }
+}
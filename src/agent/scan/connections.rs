@@ -1,11 +1,29 @@
 use std::io::{self, BufReader, BufRead};
-use std::fs::File;
-use std::net::{SocketAddrV4, Ipv4Addr};
+use std::fs::{File, read_dir, read_link};
+use std::sync::Arc;
+use std::path::Path;
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
 use std::collections::{HashMap, HashSet};
 use rustc_serialize::{Encoder, Encodable};
 
+use cantal::Value::{Integer, Counter};
+use history::Key;
+use super::{Tip, Roots, time_ms};
+use super::processes::{Pid, MinimalProcess};
+use super::cgroups::CGroups;
+
 
 const MAX_CONNECTION_DETAILS: usize = 1000;
+/// Minimum interval between rescans of file descriptors of all processes,
+/// rescan is only done if there are sockets with unknown owner
+const FD_RESCAN_INTERVAL: u64 = 30000;
+
+
+pub struct ReadCache {
+    /// Socket inode to the pid of the owning process
+    inodes: HashMap<u64, Pid>,
+    last_rescan: u64,
+}
 
 
 #[derive(RustcEncodable, Debug)]
@@ -32,7 +50,7 @@ pub trait MyEncodable {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error>;
 }
 
-impl MyEncodable for SocketAddrV4 {
+impl MyEncodable for SocketAddr {
     fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
         s.emit_str(&format!("{}", self))
     }
@@ -40,12 +58,15 @@ impl MyEncodable for SocketAddrV4 {
 
 #[derive(RustcEncodable, Debug, Clone)]
 pub struct Socket {
-    pub local_address: SocketAddrV4,
-    pub remote_address: SocketAddrV4,
+    pub local_address: SocketAddr,
+    pub remote_address: SocketAddr,
     pub state: State,
     pub tx_queue: usize,
     pub rx_queue: usize,
     pub uid: u32,
+    pub inode: u64,
+    pub pid: Option<Pid>,
+    pub cgroup: Option<Arc<String>>,
 }
 
 #[allow(dead_code, non_camel_case_types)]
@@ -65,12 +86,53 @@ pub enum State {
     CLOSING,
 }
 
+/// Statistics of UDP sockets bound to the same local port, or of all
+/// connected UDP sockets
+#[derive(RustcEncodable, Debug)]
+pub struct UdpStats {
+    pub sockets: usize,
+    pub rx_queue: usize,
+    pub tx_queue: usize,
+    /// Number of datagrams dropped because receive queue was full
+    pub drops: u64,
+}
+
+#[allow(dead_code, non_camel_case_types)]
+#[derive(Clone, Copy, Debug, RustcEncodable, Hash, PartialEq, Eq)]
+pub enum UnixState {
+    FREE = 0,
+    UNCONNECTED,
+    CONNECTING,
+    CONNECTED,
+    DISCONNECTING,
+}
+
+/// Statistics of unix sockets with the same path
+///
+/// Kernel doesn't expose queue sizes of unix sockets in `/proc/net/unix`,
+/// but accepted connections have the same path as the listening socket,
+/// so we can at least count them.
+#[derive(RustcEncodable, Debug)]
+pub struct UnixStats {
+    pub listening: usize,
+    pub by_state: HashMap<UnixState, usize>,
+}
+
 #[derive(RustcEncodable, Debug)]
 pub struct Connections {
     pub global: Stats,
     pub by_user: HashMap<u32, Stats>,
     pub passive: HashMap<u32, HashMap<u16, Passive>>,
     pub active: HashMap<u32, HashMap<u16, Active>>,
+    /// TCP sockets by cgroup of the owning process
+    pub by_cgroup: HashMap<String, Stats>,
+    /// Unconnected UDP sockets by local port
+    pub udp: HashMap<u16, UdpStats>,
+    /// Connected UDP sockets, local ports of them are usually ephemeral,
+    /// so they are not split by port. `None` if UDP table can't be read
+    pub udp_connected: Option<UdpStats>,
+    /// Unix sockets by path, unnamed sockets are not included
+    pub unix: HashMap<String, UnixStats>,
 }
 
 impl Stats {
@@ -108,6 +170,45 @@ impl From<u8> for State {
     }
 }
 
+impl From<u8> for UnixState {
+    fn from(x: u8) -> UnixState {
+        use self::UnixState::*;
+        match x {
+            1 => UNCONNECTED,
+            2 => CONNECTING,
+            3 => CONNECTED,
+            4 => DISCONNECTING,
+            _ => FREE,
+        }
+    }
+}
+
+impl UdpStats {
+    fn new() -> UdpStats {
+        UdpStats {
+            sockets: 0,
+            rx_queue: 0,
+            tx_queue: 0,
+            drops: 0,
+        }
+    }
+    fn add(&mut self, sock: &Socket, drops: u64) {
+        self.sockets += 1;
+        self.rx_queue += sock.rx_queue;
+        self.tx_queue += sock.tx_queue;
+        self.drops += drops;
+    }
+}
+
+impl UnixStats {
+    fn new() -> UnixStats {
+        UnixStats {
+            listening: 0,
+            by_state: HashMap::new(),
+        }
+    }
+}
+
 impl Active {
     fn new() -> Active {
         Active {
@@ -147,30 +248,55 @@ impl Passive {
     }
 }
 
-/// Parses socket addr defined in /proc/net/tcp
+/// Parses socket addr defined in /proc/net/tcp or /proc/net/tcp6
 /// It hexadecimal ip:port so it's fixed size
-fn parse_addr(val: &str) -> Option<SocketAddrV4> {
-    if val.len() != 13 {
-        return None;
-    }
-    let port = match u16::from_str_radix(&val[9..13], 16) {
-        Ok(x) => x,
-        Err(..) => return None,
+///
+/// IPv4-mapped IPv6 addresses are converted to IPv4 ones, so that
+/// connections to dual-stack listeners are merged with IPv4 connections
+fn parse_addr(val: &str) -> Option<SocketAddr> {
+    let ip_len = match val.len() {
+        13 => 8,
+        37 => 32,
+        _ => return None,
     };
-    let ip = match u32::from_str_radix(&val[..8], 16) {
-        Ok(x) => u32::from_be(x),
+    let port = match u16::from_str_radix(&val[ip_len+1..], 16) {
+        Ok(x) => x,
         Err(..) => return None,
     };
-    Some(SocketAddrV4::new(Ipv4Addr::from(ip), port))
+    // Address is printed as a sequence of 32-bit words in host byte order
+    let mut words = [0u32; 4];
+    for (i, word) in words[..ip_len/8].iter_mut().enumerate() {
+        match u32::from_str_radix(&val[i*8..i*8+8], 16) {
+            Ok(x) => *word = u32::from_be(x),
+            Err(..) => return None,
+        }
+    }
+    if ip_len == 8 {
+        return Some(SocketAddr::V4(
+            SocketAddrV4::new(Ipv4Addr::from(words[0]), port)));
+    }
+    let ip = Ipv6Addr::new(
+        (words[0] >> 16) as u16, words[0] as u16,
+        (words[1] >> 16) as u16, words[1] as u16,
+        (words[2] >> 16) as u16, words[2] as u16,
+        (words[3] >> 16) as u16, words[3] as u16);
+    match ip.to_ipv4() {
+        Some(ip4) if words[2] == 0xFFFF => {
+            Some(SocketAddr::V4(SocketAddrV4::new(ip4, port)))
+        }
+        _ => Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0))),
+    }
 }
 
 fn parse_line(line: &str) -> Socket {
     let mut pieces = line.split_whitespace();
     pieces.next(); // Skip slot number
     let local = pieces.next().and_then(parse_addr)
-        .unwrap_or(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0));
+        .unwrap_or(SocketAddr::V4(
+            SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)));
     let remote = pieces.next().and_then(parse_addr)
-        .unwrap_or(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0));
+        .unwrap_or(SocketAddr::V4(
+            SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)));
     let status = pieces.next()
         .and_then(|x| u8::from_str_radix(x, 16).ok())
         .unwrap_or(0);
@@ -181,9 +307,13 @@ fn parse_line(line: &str) -> Socket {
     let rx = queues.next()
         .and_then(|x| usize::from_str_radix(x, 16).ok())
         .unwrap_or(0);
-    let uid = pieces.skip(2).next()
+    let mut pieces = pieces.skip(2);
+    let uid = pieces.next()
         .and_then(|x| x.parse::<u32>().ok())
         .unwrap_or(0);
+    let inode = pieces.nth(1)  // skip timeout
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(0);
     return Socket {
         local_address: local,
         remote_address: remote,
@@ -191,19 +321,193 @@ fn parse_line(line: &str) -> Socket {
         tx_queue: tx,
         rx_queue: rx,
         uid: uid,
+        inode: inode,
+        pid: None,
+        cgroup: None,
     }
 }
 
-pub fn read() -> Option<Connections> {
-    _read().map_err(|e| error!("Can't read tcp connections: {}", e)).ok()
+/// Parses line of /proc/net/udp, which has the same fields as tcp one,
+/// and number of drops in the last column
+fn parse_udp_line(line: &str) -> (Socket, u64) {
+    let drops = line.split_whitespace().nth(12)
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(0);
+    (parse_line(line), drops)
+}
+
+/// Parses line of /proc/net/unix, returns path, state and whether
+/// socket is listening
+fn parse_unix_line(line: &str) -> Option<(&str, UnixState, bool)> {
+    // Columns are padded with spaces, and path (the last column) may
+    // contain spaces too, so we can't just split the line
+    let mut rest = line.trim_right();
+    let mut fields = [""; 7];
+    for field in fields.iter_mut() {
+        rest = rest.trim_left();
+        let end = rest.find(' ').unwrap_or(rest.len());
+        *field = &rest[..end];
+        rest = &rest[end..];
+    }
+    let flags = match u32::from_str_radix(fields[3], 16) {
+        Ok(x) => x,
+        Err(_) => return None,
+    };
+    let state = match u8::from_str_radix(fields[5], 16) {
+        Ok(x) => UnixState::from(x),
+        Err(_) => return None,
+    };
+    // __SO_ACCEPTCON flag
+    let listening = flags & 0x10000 != 0;
+    if rest.len() < 2 {
+        // unnamed socket
+        return None;
+    }
+    Some((&rest[1..], state, listening))
 }
 
-fn _read() -> io::Result<Connections> {
+/// Reads a table from /proc/net skipping the header
+///
+/// Returns no error for optional files which don't exist (ipv6 tables
+/// are absent when ipv6 is disabled in kernel)
+fn read_table<F>(path: &Path, optional: bool, mut f: F) -> io::Result<()>
+    where F: FnMut(&str)
+{
+    let mut file = match File::open(path) {
+        Ok(file) => BufReader::new(file),
+        Err(ref e) if optional && e.kind() == io::ErrorKind::NotFound => {
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
     let mut line = String::with_capacity(200);
-    let mut file = BufReader::new(try!(File::open("/proc/net/tcp")));
     try!(file.read_line(&mut line)); // header
+    loop {
+        line.clear();
+        try!(file.read_line(&mut line));
+        if line.len() == 0 { break; }
+        f(&line);
+    }
+    Ok(())
+}
+
+/// Returns unconnected sockets by local port and all connected sockets
+fn read_udp(roots: &Roots)
+    -> io::Result<(HashMap<u16, UdpStats>, UdpStats)>
+{
+    let mut udp = HashMap::new();
+    let mut connected = UdpStats::new();
+    {
+        let mut add = |line: &str| {
+            let (sock, drops) = parse_udp_line(line);
+            if sock.remote_address.port() != 0 {
+                connected.add(&sock, drops);
+            } else {
+                udp.entry(sock.local_address.port())
+                    .or_insert_with(UdpStats::new)
+                    .add(&sock, drops);
+            }
+        };
+        try!(read_table(&roots.proc_path("net/udp"), false, &mut add));
+        try!(read_table(&roots.proc_path("net/udp6"), true, &mut add));
+    }
+    Ok((udp, connected))
+}
+
+fn read_unix(roots: &Roots) -> io::Result<HashMap<String, UnixStats>> {
+    let mut unix = HashMap::new();
+    try!(read_table(&roots.proc_path("net/unix"), false, |line| {
+        if let Some((path, state, listening)) = parse_unix_line(line) {
+            let stats = unix.entry(path.to_string())
+                .or_insert_with(UnixStats::new);
+            if listening {
+                stats.listening += 1;
+            } else {
+                *stats.by_state.entry(state).or_insert(0) += 1;
+            }
+        }
+    }));
+    Ok(unix)
+}
+
+/// Parses link of file descriptor like `socket:[12345]`
+fn parse_socket_link(val: &str) -> Option<u64> {
+    if val.starts_with("socket:[") && val.ends_with("]") {
+        val["socket:[".len()..val.len()-1].parse().ok()
+    } else {
+        None
+    }
+}
+
+fn read_socket_inodes(roots: &Roots, pid: Pid,
+    inodes: &mut HashMap<u64, Pid>)
+{
+    let dir = match read_dir(roots.pid_path(pid, "fd")) {
+        Ok(dir) => dir,
+        // Process is either dead or we have no permissions
+        Err(e) => {
+            debug!("Can't read fds of {}: {}", pid, e);
+            return;
+        }
+    };
+    for entry in dir.filter_map(|x| x.ok()) {
+        if let Ok(link) = read_link(entry.path()) {
+            if let Some(inode) = link.to_str().and_then(parse_socket_link) {
+                inodes.insert(inode, pid);
+            }
+        }
+    }
+}
+
+fn resolve_owners(cache: &mut ReadCache, sockets: &mut Vec<Socket>,
+    processes: &[MinimalProcess], cgroups: &CGroups, roots: &Roots)
+{
+    // Sockets of dead processes are either closed or inherited by another
+    // process, in both cases the owner isn't known any more
+    let alive = processes.iter().map(|p| p.pid).collect::<HashSet<_>>();
+    let dead = cache.inodes.iter()
+        .filter(|&(_, pid)| !alive.contains(pid))
+        .map(|(&inode, _)| inode)
+        .collect::<Vec<_>>();
+    for inode in dead {
+        cache.inodes.remove(&inode);
+    }
+    // Sockets in TIME_WAIT and some other states have no inode
+    let unknown = sockets.iter()
+        .any(|s| s.inode != 0 && !cache.inodes.contains_key(&s.inode));
+    let now = time_ms();
+    if unknown && now.saturating_sub(cache.last_rescan) >= FD_RESCAN_INTERVAL
+    {
+        // Rebuild from scratch, so closed sockets are forgotten
+        cache.inodes.clear();
+        for prc in processes {
+            read_socket_inodes(roots, prc.pid, &mut cache.inodes);
+        }
+        cache.last_rescan = now;
+    }
+    for sock in sockets.iter_mut() {
+        if let Some(&pid) = cache.inodes.get(&sock.inode) {
+            sock.pid = Some(pid);
+            sock.cgroup = cgroups.get(&pid).cloned();
+        }
+    }
+}
+
+pub fn read(cache: &mut ReadCache, processes: &[MinimalProcess],
+    cgroups: &CGroups, roots: &Roots)
+    -> Option<Connections>
+{
+    _read(cache, processes, cgroups, roots)
+    .map_err(|e| error!("Can't read connections: {}", e)).ok()
+}
+
+fn _read(cache: &mut ReadCache, processes: &[MinimalProcess],
+    cgroups: &CGroups, roots: &Roots)
+    -> io::Result<Connections>
+{
     let mut stats = Stats::new();
     let mut by_user = HashMap::new();
+    let mut by_cgroup = HashMap::new();
     // Passive are accepted connections on listening socket
     let mut passive = HashMap::new();
     // Active are connections that are established to a remote host
@@ -215,15 +519,23 @@ fn _read() -> io::Result<Connections> {
     // Sockets that are unknown to be active or passive
     // If we see a duplicate address at either side we know that the
     // addresss is use for active or passive socket for sure.
-    let mut local_unknown = HashMap::<SocketAddrV4, Socket>::new();
-    let mut remote_unknown = HashMap::<SocketAddrV4, Socket>::new();
-    loop {
-        line.clear();
-        try!(file.read_line(&mut line));
-        if line.len() == 0 { break; }
-        let sock = parse_line(&line);
+    let mut local_unknown = HashMap::<SocketAddr, Socket>::new();
+    let mut remote_unknown = HashMap::<SocketAddr, Socket>::new();
+    // Both files are read into the same maps, so IPv4 and IPv6 sockets
+    // on the same port are merged
+    let mut sockets = Vec::new();
+    try!(read_table(&roots.proc_path("net/tcp"), false,
+        |line| sockets.push(parse_line(line))));
+    try!(read_table(&roots.proc_path("net/tcp6"), true,
+        |line| sockets.push(parse_line(line))));
+    resolve_owners(cache, &mut sockets, processes, cgroups, roots);
+    for sock in sockets.into_iter() {
         stats.add(&sock);
         by_user.entry(sock.uid).or_insert_with(Stats::new).add(&sock);
+        if let Some(ref cgroup) = sock.cgroup {
+            by_cgroup.entry(cgroup.to_string()).or_insert_with(Stats::new)
+                .add(&sock);
+        }
 
         let la = sock.local_address;
         let ra = sock.remote_address;
@@ -270,11 +582,182 @@ fn _read() -> io::Result<Connections> {
             .entry(sock.remote_address.port())
             .or_insert_with(Active::new).add(sock);
     }
+    // TCP statistics are still useful if other tables can't be read
+    let (udp, udp_connected) = match read_udp(roots) {
+        Ok((udp, connected)) => (udp, Some(connected)),
+        Err(e) => {
+            error!("Can't read udp sockets: {}", e);
+            (HashMap::new(), None)
+        }
+    };
+    let unix = read_unix(roots).map_err(|e| {
+        error!("Can't read unix sockets: {}", e);
+    }).unwrap_or_else(|()| HashMap::new());
 
     Ok(Connections {
         global: stats,
         by_user: by_user,
         active: active,
         passive: passive,
+        by_cgroup: by_cgroup,
+        udp: udp,
+        udp_connected: udp_connected,
+        unix: unix,
     })
 }
+
+fn write_udp<F: Fn(&str) -> Key>(tip: &mut Tip, stats: &UdpStats, key: F)
+{
+    tip.add(key("udp.sockets"), Integer(stats.sockets as i64));
+    tip.add(key("udp.rx_queue"), Integer(stats.rx_queue as i64));
+    tip.add(key("udp.tx_queue"), Integer(stats.tx_queue as i64));
+    tip.add(key("udp.drops"), Counter(stats.drops));
+}
+
+pub fn write_tip(tip: &mut Tip, connections: &Connections) {
+    for (cgroup, stats) in connections.by_cgroup.iter() {
+        for (state, &num) in stats.by_state.iter() {
+            tip.add(Key::pairs(&[
+                    ("cgroup", cgroup),
+                    ("metric", "tcp.sockets"),
+                    ("tcp_state", &format!("{:?}", state)),
+                ]), Integer(num as i64));
+        }
+        tip.add(Key::pairs(&[
+                ("cgroup", cgroup),
+                ("metric", "tcp.rx_queue"),
+            ]), Integer(stats.rx_queue as i64));
+        tip.add(Key::pairs(&[
+                ("cgroup", cgroup),
+                ("metric", "tcp.tx_queue"),
+            ]), Integer(stats.tx_queue as i64));
+    }
+    for (port, stats) in connections.udp.iter() {
+        let port = port.to_string();
+        write_udp(tip, stats, |metric| Key::pairs(&[
+                ("metric", metric),
+                ("port", &port),
+            ]));
+    }
+    if let Some(ref stats) = connections.udp_connected {
+        write_udp(tip, stats, |metric| Key::pairs(&[
+                ("metric", metric),
+                ("udp_state", "connected"),
+            ]));
+    }
+    for (path, stats) in connections.unix.iter() {
+        tip.add(Key::pairs(&[
+                ("metric", "unix.listening"),
+                ("path", path),
+            ]), Integer(stats.listening as i64));
+        tip.add(Key::pairs(&[
+                ("metric", "unix.connected"),
+                ("path", path),
+            ]), Integer(stats.by_state.get(&UnixState::CONNECTED)
+                        .map(|&x| x as i64).unwrap_or(0)));
+    }
+}
+
+impl ReadCache {
+    pub fn new() -> ReadCache {
+        ReadCache {
+            inodes: HashMap::new(),
+            last_rescan: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use super::{parse_addr, parse_udp_line, parse_unix_line, UnixState};
+    use super::{parse_line, parse_socket_link, resolve_owners, ReadCache};
+    use scan::test::fixture;
+    use scan::{cgroups, processes};
+
+    fn addr(x: &str) -> Option<SocketAddr> {
+        Some(SocketAddr::from_str(x).unwrap())
+    }
+
+    #[test]
+    fn ipv4() {
+        assert_eq!(parse_addr("0100007F:0050"), addr("127.0.0.1:80"));
+        assert_eq!(parse_addr("00000000:1F90"), addr("0.0.0.0:8080"));
+        assert_eq!(parse_addr("0100007F:005"), None);
+    }
+
+    #[test]
+    fn ipv6() {
+        assert_eq!(parse_addr("00000000000000000000000001000000:0050"),
+                   addr("[::1]:80"));
+        assert_eq!(parse_addr("B80D0120000000000000000001000000:01BB"),
+                   addr("[2001:db8::1]:443"));
+        // IPv4-mapped
+        assert_eq!(parse_addr("0000000000000000FFFF00000100007F:0050"),
+                   addr("127.0.0.1:80"));
+    }
+
+    #[test]
+    fn udp_drops() {
+        let (sock, drops) = parse_udp_line(
+            "  123: 00000000:0035 00000000:0000 07 00000000:00000A00 \
+             00:00000000 00000000   101        0 15843 2 \
+             ffff880036fa3c00 42");
+        assert_eq!(sock.local_address.port(), 53);
+        assert_eq!(sock.rx_queue, 0xA00);
+        assert_eq!(sock.uid, 101);
+        assert_eq!(drops, 42);
+    }
+
+    #[test]
+    fn unix() {
+        assert_eq!(parse_unix_line(
+            "ffff8800b9bc1c00: 00000002 00000000 00010000 0001 01 \
+             17331 /run/my app.sock\n"),
+            Some(("/run/my app.sock", UnixState::UNCONNECTED, true)));
+        assert_eq!(parse_unix_line(
+            "ffff8800b9bc0000: 00000003 00000000 00000000 0001 03 \
+             17332 /run/my app.sock\n"),
+            Some(("/run/my app.sock", UnixState::CONNECTED, false)));
+        assert_eq!(parse_unix_line(
+            "ffff8800b9bc0400: 00000003 00000000 00000000 0001 03 17333\n"),
+            None);
+    }
+
+    #[test]
+    fn tcp_inode() {
+        let sock = parse_line(
+            "   0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 \
+             00:00000000 00000000  1000        0 27474 1 \
+             ffff8800b8a80000 100 0 0 10 0");
+        assert_eq!(sock.uid, 1000);
+        assert_eq!(sock.inode, 27474);
+    }
+
+    #[test]
+    fn dead_owner() {
+        let roots = fixture();
+        let cgroups = cgroups::read(&roots);
+        let prcs = processes::read(&mut processes::ReadCache::new(),
+                                   &cgroups, &roots);
+        let mut cache = ReadCache::new();
+        // the socket was owned by a process which is dead now
+        cache.inodes.insert(27474, 1000);
+        let mut sockets = vec![parse_line(
+            "   0: 0100007F:0CEA 00000000:0000 0A 00000000:00000000 \
+             00:00000000 00000000  1000        0 27474 1 \
+             ffff8800b8a80000 100 0 0 10 0")];
+        resolve_owners(&mut cache, &mut sockets, &prcs, &cgroups, &roots);
+        assert_eq!(sockets[0].pid, Some(42));
+        assert_eq!(sockets[0].cgroup.as_ref().map(|x| &x[..]),
+                   Some("system.nscd"));
+    }
+
+    #[test]
+    fn socket_link() {
+        assert_eq!(parse_socket_link("socket:[27474]"), Some(27474));
+        assert_eq!(parse_socket_link("pipe:[27474]"), None);
+        assert_eq!(parse_socket_link("/dev/null"), None);
+    }
+}
@@ -0,0 +1,171 @@
+use std::mem;
+use std::thread;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use std::collections::HashSet;
+use std::os::unix::ffi::OsStrExt;
+
+use libc;
+
+use cantal::Value::Integer;
+use history::Key;
+use super::{Tip, Roots};
+use super::mountinfo;
+
+
+/// Filesystems which have no meaningful capacity
+const PSEUDO_FILESYSTEMS: &'static [&'static str] = &[
+    "autofs", "binfmt_misc", "bpf", "cgroup", "cgroup2", "configfs",
+    "debugfs", "devpts", "devtmpfs", "fusectl", "hugetlbfs", "iso9660",
+    "mqueue", "nsfs", "proc", "pstore", "rpc_pipefs",
+    "securityfs", "selinuxfs", "squashfs", "sysfs", "tracefs",
+];
+
+/// Filesystems for which `statvfs` blocks while the server is unreachable
+const NETWORK_FILESYSTEMS: &'static [&'static str] = &[
+    "9p", "afs", "ceph", "cifs", "fuse.glusterfs", "fuse.sshfs",
+    "glusterfs", "lustre", "ncpfs", "nfs", "nfs4", "smb3", "smbfs",
+];
+
+/// Milliseconds to wait for `statvfs` of a network filesystem
+const NETWORK_TIMEOUT: u64 = 1000;
+
+pub struct ReadCache {
+    /// Mount points where `statvfs` is still running, they are skipped
+    /// until it finishes
+    hung: Arc<Mutex<HashSet<PathBuf>>>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    bytes_total: u64,
+    bytes_free: u64,
+    bytes_available: u64,
+    inodes_total: u64,
+    inodes_free: u64,
+    inodes_available: u64,
+}
+
+fn stat(path: &Path) -> Option<Usage> {
+    let cpath = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(x) => x,
+        Err(_) => return None,
+    };
+    let mut st: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(cpath.as_ptr(), &mut st) } != 0 {
+        debug!("Can't statvfs {:?}", path);
+        return None;
+    }
+    let frsize = st.f_frsize as u64;
+    Some(Usage {
+        bytes_total: st.f_blocks as u64 * frsize,
+        bytes_free: st.f_bfree as u64 * frsize,
+        bytes_available: st.f_bavail as u64 * frsize,
+        inodes_total: st.f_files as u64,
+        inodes_free: st.f_ffree as u64,
+        inodes_available: st.f_favail as u64,
+    })
+}
+
+/// Runs `statvfs` in a thread, so unreachable server doesn't block scan
+fn stat_network(cache: &ReadCache, path: &Path) -> Option<Usage> {
+    if !cache.hung.lock().unwrap().insert(path.to_path_buf()) {
+        debug!("Still waiting for statvfs {:?}", path);
+        return None;
+    }
+    let (tx, rx) = channel();
+    let hung = cache.hung.clone();
+    let tpath = path.to_path_buf();
+    thread::spawn(move || {
+        let usage = stat(&tpath);
+        hung.lock().unwrap().remove(&tpath);
+        tx.send(usage).ok();
+    });
+    match rx.recv_timeout(Duration::from_millis(NETWORK_TIMEOUT)) {
+        Ok(usage) => usage,
+        Err(_) => {
+            warn!("Statvfs {:?} takes more than {} ms, skipping",
+                  path, NETWORK_TIMEOUT);
+            None
+        }
+    }
+}
+
+/// Records capacity of every real filesystem mounted on the host
+///
+/// Filesystems are accessed through `/proc/1/root`, so this works when
+/// the agent runs in a container with host's `/proc`. Network filesystems
+/// are checked in a separate thread with a timeout, and are skipped until
+/// the previous check finishes.
+pub fn read(t: &mut Tip, cache: &ReadCache, roots: &Roots) {
+    for mount in mountinfo::read(roots) {
+        if PSEUDO_FILESYSTEMS.contains(&&mount.fstype[..]) {
+            continue;
+        }
+        let path = roots.host_path(&mount.mount_point);
+        let usage = if NETWORK_FILESYSTEMS.contains(&&mount.fstype[..]) {
+            stat_network(cache, &path)
+        } else {
+            stat(&path)
+        };
+        let usage = match usage {
+            Some(usage) => usage,
+            None => continue,
+        };
+        if usage.bytes_total == 0 {
+            // Some virtual filesystems have no type we know of
+            continue;
+        }
+        let mountpoint = mount.mount_point.to_string_lossy();
+        let values = [
+            ("fs.bytes.total", usage.bytes_total),
+            ("fs.bytes.free", usage.bytes_free),
+            ("fs.bytes.available", usage.bytes_available),
+            ("fs.inodes.total", usage.inodes_total),
+            ("fs.inodes.free", usage.inodes_free),
+            ("fs.inodes.available", usage.inodes_available),
+        ];
+        for &(metric, value) in values.iter() {
+            t.add(Key::pairs(&[
+                    ("device", &mount.source[..]),
+                    ("metric", metric),
+                    ("mountpoint", &mountpoint[..]),
+                ]), Integer(value as i64));
+        }
+    }
+}
+
+impl ReadCache {
+    pub fn new() -> ReadCache {
+        ReadCache {
+            hung: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::{stat, stat_network, ReadCache};
+
+    #[test]
+    fn root() {
+        let usage = stat(Path::new("/")).unwrap();
+        assert!(usage.bytes_total >= usage.bytes_free);
+        assert!(usage.bytes_free >= usage.bytes_available);
+        assert!(stat(Path::new("/nonexistent/directory")).is_none());
+    }
+
+    #[test]
+    fn network() {
+        let cache = ReadCache::new();
+        assert!(stat_network(&cache, Path::new("/")).is_some());
+        assert!(cache.hung.lock().unwrap().is_empty());
+        // the same path is skipped while statvfs is running
+        cache.hung.lock().unwrap().insert(Path::new("/").to_path_buf());
+        assert!(stat_network(&cache, Path::new("/")).is_none());
+    }
+}
@@ -0,0 +1,559 @@
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+use quire::validate::{Structure, Sequence, Scalar};
+
+use cantal::Value::{Counter, Integer};
+use history::Key;
+use super::Tip;
+use super::processes::{Pid, MinimalProcess};
+use super::values::{Environ, Environs};
+
+
+/// Groups which had no processes for this long are forgotten (an hour)
+const EXPIRE_MS: u64 = 3_600_000;
+
+/// Counters summed over processes of the group
+///
+/// Last values of the exited processes are kept in the group, so that
+/// counters of the group never go down.
+const COUNTERS: &'static [&'static str] = &[
+    "user_time",
+    "system_time",
+    "read_bytes",
+    "write_bytes",
+    "minor_faults",
+    "major_faults",
+    "voluntary_ctx_switches",
+    "involuntary_ctx_switches",
+];
+
+type Counters = [u64; 8];
+
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Rule {
+    pub by: Option<String>,
+    pub name: Option<String>,
+    pub cmdline: Option<String>,
+    pub exe: Option<String>,
+}
+
+/// Process grouping, read from `*.processes.yaml`
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Config {
+    pub per_pid: Option<bool>,
+    pub groups: Vec<Rule>,
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("per_pid", Scalar::new().optional())
+    .member("groups", Sequence::new(Structure::new()
+        .member("by", Scalar::new().optional())
+        .member("name", Scalar::new().optional())
+        .member("cmdline", Scalar::new().optional())
+        .member("exe", Scalar::new().optional())))
+}
+
+enum Matcher {
+    CGroup,
+    AppName,
+    Pattern {
+        name: String,
+        cmdline: Option<Regex>,
+        exe: Option<Regex>,
+    },
+}
+
+/// Rules of all configs, first matching rule determines the group
+pub struct Grouping {
+    /// Whether to report metrics of every process too
+    pub per_pid: bool,
+    /// Name of the label the group is stored in
+    label: &'static str,
+    rules: Vec<Matcher>,
+}
+
+#[derive(Default)]
+struct Group {
+    processes: u64,
+    vsize: u64,
+    rss: u64,
+    num_threads: u64,
+    swap: u64,
+    fds: u64,
+    /// Maximum over processes, summing scores makes no sense
+    oom_score: u64,
+    counters: Counters,
+}
+
+/// Sum of last counters of the processes which have exited
+struct Exited {
+    counters: Counters,
+    /// Last time (ms) the group had processes at
+    updated: u64,
+}
+
+pub struct ReadCache {
+    /// Time (ms) of the previous call to `write_tip`
+    last_run: u64,
+    /// Group and last counters of processes by pid and start time
+    processes: HashMap<(Pid, u64), (String, Counters)>,
+    exited: HashMap<String, Exited>,
+}
+
+fn counters(p: &MinimalProcess) -> Counters {
+    [
+        p.user_time as u64,
+        p.system_time as u64,
+        p.read_bytes,
+        p.write_bytes,
+        p.minor_faults,
+        p.major_faults,
+        p.voluntary_ctx_switches,
+        p.involuntary_ctx_switches,
+    ]
+}
+
+fn add(sum: &mut Counters, values: &Counters) {
+    for (s, v) in sum.iter_mut().zip(values.iter()) {
+        *s += *v;
+    }
+}
+
+fn regex(pattern: &Option<String>) -> Result<Option<Regex>, String> {
+    match *pattern {
+        Some(ref x) => Regex::new(x).map(Some).map_err(|e| e.to_string()),
+        None => Ok(None),
+    }
+}
+
+fn compile(rule: &Rule) -> Result<Matcher, String> {
+    let has_pattern = rule.cmdline.is_some() || rule.exe.is_some();
+    if let Some(ref by) = rule.by {
+        if rule.name.is_some() || has_pattern {
+            return Err("`by` can't be used with other options".to_string());
+        }
+        return match &by[..] {
+            "cgroup" => Ok(Matcher::CGroup),
+            "appname" => Ok(Matcher::AppName),
+            _ => Err(format!("unknown grouping `by: {}`", by)),
+        };
+    }
+    let name = match rule.name {
+        Some(ref name) => name.clone(),
+        None => return Err("either `by` or `name` is required".to_string()),
+    };
+    if !has_pattern {
+        return Err("either `cmdline` or `exe` is required".to_string());
+    }
+    Ok(Matcher::Pattern {
+        name: name,
+        cmdline: try!(regex(&rule.cmdline)),
+        exe: try!(regex(&rule.exe)),
+    })
+}
+
+impl Grouping {
+    /// Rules are checked in the order of configs, and `per_pid` of the
+    /// last config having it wins
+    pub fn new(configs: &[Config]) -> Grouping {
+        let mut per_pid = true;
+        let mut rules = Vec::new();
+        for cfg in configs {
+            if let Some(x) = cfg.per_pid {
+                per_pid = x;
+            }
+            for rule in &cfg.groups {
+                match compile(rule) {
+                    Ok(matcher) => rules.push(matcher),
+                    Err(e) => warn!("Bad process group {:?}: {}", rule, e),
+                }
+            }
+        }
+        Grouping {
+            per_pid: per_pid,
+            label: "group",
+            rules: rules,
+        }
+    }
+    /// Groups processes by cgroup, stored in `cgroup` label
+    ///
+    /// These are always reported, so that totals of cgroups are known
+    /// even if there are no per-process metrics.
+    pub fn cgroups() -> Grouping {
+        Grouping {
+            per_pid: true,
+            label: "cgroup",
+            rules: vec![Matcher::CGroup],
+        }
+    }
+    fn group<'x>(&'x self, p: &'x MinimalProcess, env: Option<&'x Environ>)
+        -> Option<&'x str>
+    {
+        for rule in &self.rules {
+            match *rule {
+                Matcher::CGroup => {
+                    if let Some(ref cgroup) = p.cgroup {
+                        return Some(&cgroup[..]);
+                    }
+                }
+                Matcher::AppName => {
+                    if let Some(appname) = env.and_then(|x| x.appname.as_ref())
+                    {
+                        return Some(&appname[..]);
+                    }
+                }
+                Matcher::Pattern { ref name, ref cmdline, ref exe } => {
+                    // Arguments are separated by spaces for matching
+                    let cmdline_ok = cmdline.as_ref().map_or(true, |re| {
+                        re.is_match(p.cmdline.replace("\0", " ").trim())
+                    });
+                    let exe_ok = exe.as_ref().map_or(true, |re| {
+                        p.exe.as_ref().and_then(|x| x.to_str())
+                            .map_or(false, |x| re.is_match(x))
+                    });
+                    if cmdline_ok && exe_ok {
+                        return Some(&name[..]);
+                    }
+                }
+            }
+        }
+        return None;
+    }
+}
+
+/// Writes metrics of process groups, returns processes not in any group
+///
+/// Processes which don't match any rule are not reported here. Groups
+/// which have no processes any more are still reported for `EXPIRE_MS`
+/// after `now`, so their counters don't disappear on restart.
+///
+/// Process which is `unreadable` is not counted as exited until it's gone
+/// from `/proc`, its last counters are reported meanwhile.
+pub fn write_tip<'x>(tip: &mut Tip, cache: &mut ReadCache,
+    grouping: &Grouping, processes: &'x [MinimalProcess],
+    environ: &Environs, unreadable: &HashSet<Pid>, now: u64)
+    -> Vec<&'x MinimalProcess>
+{
+    let mut groups = HashMap::<&str, Group>::new();
+    let mut alive = HashMap::with_capacity(cache.processes.len());
+    let mut ungrouped = Vec::new();
+    for p in processes {
+        let env = environ.get(&p.pid).map(|x| &**x);
+        let name = match grouping.group(p, env) {
+            Some(name) => name,
+            None => {
+                ungrouped.push(p);
+                continue;
+            }
+        };
+        let values = counters(p);
+        let grp = groups.entry(name).or_insert_with(Default::default);
+        grp.processes += 1;
+        grp.vsize += p.vsize;
+        grp.rss += p.rss;
+        grp.num_threads += p.num_threads as u64;
+        grp.swap += p.swap;
+        grp.fds += p.fd_count.unwrap_or(0) as u64;
+        grp.oom_score = max(grp.oom_score, p.oom_score.unwrap_or(0) as u64);
+        add(&mut grp.counters, &values);
+        alive.insert((p.pid, p.start_time), (name.to_string(), values));
+    }
+    let mut held = HashMap::<String, Counters>::new();
+    for (key, (name, values)) in cache.processes.drain() {
+        let (found, same_group) = match alive.get(&key) {
+            Some(&(ref new_name, _)) => (true, *new_name == name),
+            None => (false, false),
+        };
+        if same_group {
+            continue;
+        }
+        if !found && unreadable.contains(&key.0) {
+            add(held.entry(name.clone()).or_insert([0; 8]), &values);
+            alive.insert(key, (name, values));
+            continue;
+        }
+        // Process that has changed the group is counted as exited one
+        let last_run = cache.last_run;
+        let exited = cache.exited.entry(name).or_insert_with(|| Exited {
+            counters: [0; 8],
+            // the process was there on previous scan
+            updated: last_run,
+        });
+        add(&mut exited.counters, &values);
+    }
+    cache.processes = alive;
+
+    let mut expired = Vec::new();
+    for (name, exited) in cache.exited.iter_mut() {
+        let name: &str = name;
+        if groups.contains_key(&name) || held.contains_key(name) {
+            exited.updated = now;
+        } else if now.saturating_sub(exited.updated) >= EXPIRE_MS {
+            expired.push(name.to_string());
+        }
+    }
+    for name in expired {
+        cache.exited.remove(&name);
+    }
+    cache.last_run = now;
+
+    let empty = Group::default();
+    let mut names = groups.keys().cloned().collect::<Vec<_>>();
+    names.extend(cache.exited.keys().chain(held.keys()).map(|x| &x[..])
+        .filter(|x| !groups.contains_key(x)));
+    names.sort();
+    names.dedup();
+    for name in names {
+        let grp = groups.get(name).unwrap_or(&empty);
+        let key = |metric: &str| Key::pairs(&[
+            (grouping.label, name),
+            ("metric", metric),
+        ]);
+        tip.add(key("processes"), Integer(grp.processes as i64));
+        tip.add(key("vsize"), Integer(grp.vsize as i64));
+        tip.add(key("rss"), Integer(grp.rss as i64));
+        tip.add(key("num_threads"), Integer(grp.num_threads as i64));
+        tip.add(key("swap"), Integer(grp.swap as i64));
+        tip.add(key("fds"), Integer(grp.fds as i64));
+        tip.add(key("oom_score"), Integer(grp.oom_score as i64));
+        let mut total = grp.counters;
+        if let Some(exited) = cache.exited.get(name) {
+            add(&mut total, &exited.counters);
+        }
+        if let Some(values) = held.get(name) {
+            add(&mut total, values);
+        }
+        for (&metric, &value) in COUNTERS.iter().zip(total.iter()) {
+            tip.add(key(metric), Counter(value));
+        }
+    }
+    return ungrouped;
+}
+
+impl ReadCache {
+    pub fn new() -> ReadCache {
+        ReadCache {
+            last_run: 0,
+            processes: HashMap::new(),
+            exited: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::path::PathBuf;
+    use std::collections::{HashMap, HashSet};
+
+    use cantal::Value;
+    use history::Key;
+    use scan::Tip;
+    use scan::processes::MinimalProcess;
+    use super::{Grouping, Config, Rule, ReadCache, write_tip, EXPIRE_MS};
+
+    fn process(pid: u32, cmdline: &str, cgroup: Option<&str>,
+        user_time: u32)
+        -> MinimalProcess
+    {
+        MinimalProcess {
+            pid: pid,
+            ppid: 1,
+            uid: 0,
+            gid: 0,
+            name: "test".to_string(),
+            state: 'S',
+            vsize: 1000,
+            rss: 100,
+            num_threads: 1,
+            start_time: 5000,
+            user_time: user_time,
+            system_time: 0,
+            child_user_time: 0,
+            child_system_time: 0,
+            cmdline: cmdline.to_string(),
+            read_bytes: 0,
+            write_bytes: 0,
+            minor_faults: 0,
+            major_faults: 0,
+            voluntary_ctx_switches: 0,
+            involuntary_ctx_switches: 0,
+            swap: 0,
+            fd_count: Some(3),
+            fd_limit: None,
+            oom_score: Some(pid),
+            cgroup: cgroup.map(|x| Arc::new(x.to_string())),
+            exe: Some(PathBuf::from("/usr/bin/python3")),
+        }
+    }
+
+    fn rule(by: Option<&str>, name: Option<&str>, cmdline: Option<&str>)
+        -> Rule
+    {
+        Rule {
+            by: by.map(|x| x.to_string()),
+            name: name.map(|x| x.to_string()),
+            cmdline: cmdline.map(|x| x.to_string()),
+            exe: None,
+        }
+    }
+
+    fn grouping() -> Grouping {
+        Grouping::new(&[Config {
+            per_pid: Some(false),
+            groups: vec![
+                rule(None, Some("celery"), Some("^python3 -m celery ")),
+                rule(Some("cgroup"), None, None),
+                // invalid ones are skipped
+                rule(Some("cgroup"), Some("x"), None),
+                rule(None, Some("x"), None),
+                rule(None, Some("x"), Some("(")),
+            ],
+        }])
+    }
+
+    fn get(tip: &Tip, group: &str, metric: &str) -> Option<Value> {
+        tip.map.get(&Key::pairs(&[
+            ("group", group),
+            ("metric", metric),
+        ])).cloned()
+    }
+
+    #[test]
+    fn cgroups() {
+        let grouping = Grouping::cgroups();
+        let environ = Arc::new(HashMap::new());
+        let mut cache = ReadCache::new();
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[
+            process(10, "python3\0-m\0celery\0", Some("web"), 100),
+            process(11, "nginx\0", Some("web"), 50),
+        ], &environ, &HashSet::new(), 0);
+        assert!(get(&tip, "web", "processes").is_none());
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                ("cgroup", "web"),
+                ("metric", "processes"),
+            ])), Some(&Value::Integer(2))));
+    }
+
+    #[test]
+    fn rules() {
+        let grouping = grouping();
+        assert!(!grouping.per_pid);
+        assert_eq!(grouping.rules.len(), 2);
+        let p = process(1, "python3\0-m\0celery\0worker\0", None, 0);
+        assert_eq!(grouping.group(&p, None), Some("celery"));
+        let p = process(1, "python3\0-m\0celery\0worker\0", Some("web"), 0);
+        assert_eq!(grouping.group(&p, None), Some("celery"));
+        let p = process(1, "nginx\0", Some("web"), 0);
+        assert_eq!(grouping.group(&p, None), Some("web"));
+        let p = process(1, "nginx\0", None, 0);
+        assert_eq!(grouping.group(&p, None), None);
+    }
+
+    #[test]
+    fn counters_continue() {
+        let grouping = grouping();
+        let environ = Arc::new(HashMap::new());
+        let mut cache = ReadCache::new();
+        let mut tip = Tip::new();
+        let processes = [
+            process(10, "nginx\0", Some("web"), 100),
+            process(11, "nginx\0", Some("web"), 50),
+            process(12, "nginx\0", None, 50),
+        ];
+        let ungrouped = write_tip(&mut tip, &mut cache, &grouping,
+            &processes, &environ, &HashSet::new(), 0);
+        assert_eq!(ungrouped.iter().map(|p| p.pid).collect::<Vec<_>>(),
+                   vec![12]);
+        assert!(matches!(get(&tip, "web", "processes"),
+                         Some(Value::Integer(2))));
+        assert!(matches!(get(&tip, "web", "rss"),
+                         Some(Value::Integer(200))));
+        assert!(matches!(get(&tip, "web", "fds"),
+                         Some(Value::Integer(6))));
+        assert!(matches!(get(&tip, "web", "oom_score"),
+                         Some(Value::Integer(11))));
+        assert!(matches!(get(&tip, "web", "user_time"),
+                         Some(Value::Counter(150))));
+
+        // Worker 11 is restarted as 13, counter doesn't go down
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[
+            process(10, "nginx\0", Some("web"), 110),
+            process(13, "nginx\0", Some("web"), 5),
+        ], &environ, &HashSet::new(), 0);
+        assert!(matches!(get(&tip, "web", "processes"),
+                         Some(Value::Integer(2))));
+        assert!(matches!(get(&tip, "web", "user_time"),
+                         Some(Value::Counter(165))));
+
+        // Group is still reported when all processes are gone
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[], &environ,
+                  &HashSet::new(), 0);
+        assert!(matches!(get(&tip, "web", "processes"),
+                         Some(Value::Integer(0))));
+        assert!(matches!(get(&tip, "web", "user_time"),
+                         Some(Value::Counter(165))));
+    }
+
+    #[test]
+    fn unreadable_process() {
+        let grouping = grouping();
+        let environ = Arc::new(HashMap::new());
+        let mut cache = ReadCache::new();
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[
+            process(10, "nginx\0", Some("web"), 100),
+            process(11, "nginx\0", Some("web"), 50),
+        ], &environ, &HashSet::new(), 0);
+
+        // Worker 11 failed to read, its last counters are still reported
+        let unreadable = vec![11].into_iter().collect();
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[
+            process(10, "nginx\0", Some("web"), 110),
+        ], &environ, &unreadable, 0);
+        assert!(matches!(get(&tip, "web", "processes"),
+                         Some(Value::Integer(1))));
+        assert!(matches!(get(&tip, "web", "user_time"),
+                         Some(Value::Counter(160))));
+
+        // And it isn't counted twice when read again
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[
+            process(10, "nginx\0", Some("web"), 120),
+            process(11, "nginx\0", Some("web"), 60),
+        ], &environ, &HashSet::new(), 0);
+        assert!(matches!(get(&tip, "web", "user_time"),
+                         Some(Value::Counter(180))));
+    }
+
+    #[test]
+    fn expire() {
+        let grouping = grouping();
+        let environ = Arc::new(HashMap::new());
+        let mut cache = ReadCache::new();
+        write_tip(&mut Tip::new(), &mut cache, &grouping, &[
+            process(10, "nginx\0", Some("web"), 100),
+        ], &environ, &HashSet::new(), 1000);
+        // Scans may be delayed, so it's time that matters not their number
+        for &now in &[3000, 1000+EXPIRE_MS-1] {
+            let mut tip = Tip::new();
+            write_tip(&mut tip, &mut cache, &grouping, &[], &environ,
+                      &HashSet::new(), now);
+            assert!(matches!(get(&tip, "web", "processes"),
+                             Some(Value::Integer(0))));
+        }
+
+        let mut tip = Tip::new();
+        write_tip(&mut tip, &mut cache, &grouping, &[], &environ,
+                  &HashSet::new(), 1000+EXPIRE_MS);
+        assert!(get(&tip, "web", "processes").is_none());
+        assert!(cache.exited.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::{File, read_dir};
+use std::io::Read;
+use std::path::Path;
+use std::collections::HashMap;
+
+use cantal::Value::{Counter, Integer, State};
+use history::Key;
+use super::{Tip, Roots, time_ms};
+
+
+/// Error counters from `statistics` directory not in `/proc/net/dev`
+const ERROR_COUNTERS: &'static [(&'static str, &'static str)] = &[
+    ("rx_crc_errors", "net.interface.rx.crc_errors"),
+    ("rx_frame_errors", "net.interface.rx.frame_errors"),
+    ("rx_missed_errors", "net.interface.rx.missed_errors"),
+    ("rx_over_errors", "net.interface.rx.over_errors"),
+    ("tx_aborted_errors", "net.interface.tx.aborted_errors"),
+    ("tx_carrier_errors", "net.interface.tx.carrier_errors"),
+];
+
+pub struct ReadCache {
+    /// Operational state of the interface and time it was first seen
+    states: HashMap<String, (u64, String)>,
+}
+
+fn read_value<'x>(path: &Path, buf: &'x mut String) -> Option<&'x str> {
+    buf.truncate(0);
+    // Reading some files fails with EINVAL, e.g. speed of the interface
+    // which is down, so errors are expected here
+    let ok = File::open(path)
+        .and_then(|mut f| f.read_to_string(buf))
+        .is_ok();
+    if ok {
+        Some(buf.trim())
+    } else {
+        None
+    }
+}
+
+fn key(interface: &str, metric: &str) -> Key {
+    Key::pairs(&[
+        ("interface", interface),
+        ("metric", metric),
+    ])
+}
+
+fn read_interface(t: &mut Tip, cache: &mut ReadCache, dir: &Path,
+    name: &str)
+{
+    let mut buf = String::with_capacity(100);
+    // Speed is in Mbit/s and is -1 for unknown, we store bytes per second
+    // so it's easy to compare with `net.interface.rx.bytes`
+    if let Some(x) = read_value(&dir.join("speed"), &mut buf)
+        .and_then(|x| x.parse::<i64>().ok())
+    {
+        if x > 0 {
+            t.add(key(name, "net.interface.speed"),
+                Integer(x * 1000000 / 8));
+        }
+    }
+    if let Some(x) = read_value(&dir.join("mtu"), &mut buf)
+        .and_then(|x| x.parse().ok())
+    {
+        t.add(key(name, "net.interface.mtu"), Integer(x));
+    }
+    if let Some(x) = read_value(&dir.join("carrier_changes"), &mut buf)
+        .and_then(|x| x.parse().ok())
+    {
+        t.add(key(name, "net.interface.carrier_changes"), Counter(x));
+    }
+    for &(file, metric) in ERROR_COUNTERS {
+        if let Some(x) = read_value(&dir.join("statistics").join(file),
+                                    &mut buf)
+            .and_then(|x| x.parse().ok())
+        {
+            t.add(key(name, metric), Counter(x));
+        }
+    }
+    if let Some(state) = read_value(&dir.join("operstate"), &mut buf) {
+        let entry = cache.states.entry(name.to_string())
+            .or_insert_with(|| (time_ms(), state.to_string()));
+        if entry.1 != state {
+            *entry = (time_ms(), state.to_string());
+        }
+        t.add(key(name, "net.interface.operstate"), State(entry.clone()));
+    }
+}
+
+fn read_interfaces(t: &mut Tip, cache: &mut ReadCache, root: &Path) {
+    let dir = match read_dir(root) {
+        Ok(dir) => dir,
+        Err(e) => {
+            debug!("Can't read {:?}: {}", root, e);
+            return;
+        }
+    };
+    let mut seen = Vec::new();
+    for entry in dir.filter_map(|x| x.ok()) {
+        let name = match entry.file_name().to_str() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        read_interface(t, cache, &entry.path(), &name);
+        seen.push(name);
+    }
+    let gone = cache.states.keys()
+        .filter(|name| !seen.contains(name))
+        .cloned().collect::<Vec<_>>();
+    for name in gone {
+        cache.states.remove(&name);
+    }
+}
+
+pub fn read(t: &mut Tip, cache: &mut ReadCache, roots: &Roots) {
+    read_interfaces(t, cache, &roots.sys_path("class/net"));
+}
+
+impl ReadCache {
+    pub fn new() -> ReadCache {
+        ReadCache {
+            states: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cantal::Value;
+    use scan::Tip;
+    use scan::test::fixture;
+    use super::{read_interfaces, ReadCache, key};
+
+    #[test]
+    fn interfaces() {
+        let root = fixture().sys_path("class/net");
+        let mut cache = ReadCache::new();
+        let mut tip = Tip::new();
+        read_interfaces(&mut tip, &mut cache, &root);
+        let since = match tip.map.get(&key("eth0", "net.interface.operstate"))
+        {
+            Some(&Value::State((ts, ref text))) if text == "up" => ts,
+            x => panic!("Bad state {:?}", x),
+        };
+        assert!(matches!(tip.map.get(&key("eth0", "net.interface.speed")),
+                         Some(&Value::Integer(1250000000))));
+        assert!(matches!(tip.map.get(&key("eth0", "net.interface.mtu")),
+                         Some(&Value::Integer(1500))));
+        assert!(matches!(tip.map.get(&key("eth0",
+                                          "net.interface.carrier_changes")),
+                         Some(&Value::Counter(3))));
+        assert!(matches!(tip.map.get(&key("eth0",
+                                          "net.interface.rx.crc_errors")),
+                         Some(&Value::Counter(7))));
+        assert!(tip.map.get(&key("lo", "net.interface.speed")).is_none());
+
+        // Timestamp is kept while the state is the same
+        let mut tip = Tip::new();
+        read_interfaces(&mut tip, &mut cache, &root);
+        assert!(matches!(tip.map.get(&key("eth0", "net.interface.operstate")),
+                         Some(&Value::State((ts, _))) if ts == since));
+    }
+}
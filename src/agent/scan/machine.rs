@@ -1,20 +1,70 @@
 use std::str::FromStr;
 use std::fs::File;
-use std::path::Path;
 use std::io::{BufReader, Read, BufRead};
 use std::collections::HashMap;
 use std::collections::hash_map::Entry::{Vacant,Occupied};
 
 use cantal::itertools::{NextValue};
-use cantal::Value::{Counter, Integer};
+use cantal::Value::{Counter, Integer, Float};
 
-use super::Tip;
+use super::{Tip, Roots};
 use history::Key;
 
+const CPU_FIELDS: &'static [&'static str] = &[
+    "cpu.user", "cpu.nice", "cpu.system", "cpu.idle", "cpu.iowait",
+    "cpu.irq", "cpu.softirq", "cpu.steal", "cpu.guest", "cpu.guest_nice",
+];
 
-pub fn read(t: &mut Tip) -> Option<u64> {
+const VMSTAT_FIELDS: &'static [&'static str] = &[
+    "pgpgin", "pgpgout", "pswpin", "pswpout", "pgfault", "pgmajfault",
+    "oom_kill",
+];
+
+const SNMP_INTEGERS: &'static [&'static str] = &["CurrEstab"];
+const SNMP_FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("Tcp", &["ActiveOpens", "PassiveOpens", "AttemptFails", "EstabResets",
+              "CurrEstab", "InSegs", "OutSegs", "RetransSegs", "InErrs",
+              "OutRsts"]),
+    ("Udp", &["InDatagrams", "NoPorts", "InErrors", "OutDatagrams",
+              "RcvbufErrors", "SndbufErrors", "InCsumErrors"]),
+];
+
+/// Parses `/proc/pressure/<resource>`
+///
+/// The lines are:
+///  some avg10=0.00 avg60=0.00 avg300=0.00 total=0
+///  full avg10=0.00 avg60=0.00 avg300=0.00 total=0
+/// Averages are percents, total is in microseconds.
+fn parse_pressure(t: &mut Tip, resource: &str, text: &str) {
+    for line in text.lines() {
+        let mut pieces = line.split_whitespace();
+        let kind = match pieces.next() {
+            Some(x) => x,
+            None => continue,
+        };
+        for pair in pieces {
+            let mut kv = pair.splitn(2, '=');
+            let (name, value) = match (kv.next(), kv.next()) {
+                (Some(name), Some(value)) => (name, value),
+                _ => continue,
+            };
+            let key = Key::metric(&format!("pressure.{}.{}.{}",
+                                           resource, kind, name));
+            if name == "total" {
+                FromStr::from_str(value).map(|x| t.add(key, Counter(x)))
+                    .ok();
+            } else {
+                FromStr::from_str(value).map(|x| t.add(key, Float(x)))
+                    .ok();
+            }
+        }
+    }
+}
+
+
+pub fn read(t: &mut Tip, roots: &Roots) -> Option<u64> {
     let mut boot_time = None::<u64>;
-    File::open(&Path::new("/proc/uptime"))
+    File::open(roots.proc_path("uptime"))
         .and_then(|mut f| {
             let mut buf = String::with_capacity(100);
             f.read_to_string(&mut buf)
@@ -25,7 +75,7 @@ pub fn read(t: &mut Tip) -> Option<u64> {
             t.add_next_float(Key::metric("uptime"), &mut pieces);
             t.add_next_float(Key::metric("idle_time"), &mut pieces);
         }).ok();
-    File::open(&Path::new("/proc/sys/fs/file-nr"))
+    File::open(roots.proc_path("sys/fs/file-nr"))
         .and_then(|mut f| {
             let mut buf = String::with_capacity(100);
             f.read_to_string(&mut buf)
@@ -34,7 +84,7 @@ pub fn read(t: &mut Tip) -> Option<u64> {
             let mut pieces = buf.split_whitespace();
             t.add_next_int(Key::metric("open_files"), &mut pieces);
         }).ok();
-    File::open(&Path::new("/proc/loadavg"))
+    File::open(roots.proc_path("loadavg"))
         .and_then(|mut f| {
             let mut buf = String::with_capacity(100);
             f.read_to_string(&mut buf)
@@ -53,7 +103,7 @@ pub fn read(t: &mut Tip) -> Option<u64> {
                 });
             t.add_next_float(Key::metric("last_pid"), &mut pieces);
         }).ok();
-    File::open(&Path::new("/proc/stat")).and_then(|f| {
+    File::open(roots.proc_path("stat")).and_then(|f| {
         let mut f = BufReader::new(f);
         loop {
             let mut line = String::with_capacity(100);
@@ -62,23 +112,25 @@ pub fn read(t: &mut Tip) -> Option<u64> {
             if line.starts_with("cpu ") {
                 let mut pieces = line.split_whitespace();
                 pieces.next();
-                t.add_next_cnt(Key::metric("cpu.user"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.nice"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.system"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.idle"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.iowait"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.irq"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.softirq"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.steal"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.guest"), &mut pieces);
-                t.add_next_cnt(Key::metric("cpu.guest_nice"), &mut pieces);
+                for &name in CPU_FIELDS {
+                    t.add_next_cnt(Key::metric(name), &mut pieces);
+                }
+            } else if line.starts_with("cpu") {
+                let mut pieces = line.split_whitespace();
+                let cpu = &pieces.next().unwrap()["cpu".len()..];
+                for &name in CPU_FIELDS {
+                    t.add_next_cnt(Key::pairs(&[
+                                    ("cpu", cpu),
+                                    ("metric", name),
+                                    ]), &mut pieces);
+                }
             } else if line.starts_with("btime ") {
                 boot_time = FromStr::from_str(line[6..].trim()).ok();
             }
         }
         Ok(())
     }).ok();
-    File::open(&Path::new("/proc/meminfo")).and_then(|f| {
+    File::open(roots.proc_path("meminfo")).and_then(|f| {
         let mut f = BufReader::new(f);
         loop {
             let mut line = String::with_capacity(50);
@@ -107,7 +159,7 @@ pub fn read(t: &mut Tip) -> Option<u64> {
         }
         Ok(())
     }).ok();
-    File::open(&Path::new("/proc/net/dev")).and_then(|f| {
+    File::open(roots.proc_path("net/dev")).and_then(|f| {
         let mut f = BufReader::new(f);
         let mut line = String::with_capacity(200);
         try!(f.read_line(&mut line));
@@ -142,7 +194,7 @@ pub fn read(t: &mut Tip) -> Option<u64> {
         }
         Ok(())
     }).ok();
-    File::open(&Path::new("/proc/net/netstat")).and_then(|f| {
+    File::open(roots.proc_path("net/netstat")).and_then(|f| {
         let mut f = BufReader::new(f);
         loop {
             let mut header_line = String::with_capacity(2048);
@@ -170,7 +222,72 @@ pub fn read(t: &mut Tip) -> Option<u64> {
         }
         Ok(())
     }).ok();
-    File::open(&Path::new("/proc/diskstats")).and_then(|f| {
+    File::open(roots.proc_path("net/snmp")).and_then(|f| {
+        let mut f = BufReader::new(f);
+        loop {
+            let mut header_line = String::with_capacity(2048);
+            try!(f.read_line(&mut header_line));
+            if header_line.len() == 0 { break; }
+            let mut header = header_line.split_whitespace();
+
+            let mut values_line = String::with_capacity(1024);
+            try!(f.read_line(&mut values_line));
+            if values_line.len() == 0 { break; }
+            let mut values = values_line.split_whitespace();
+
+            let first = header.next();
+            if first != values.next() {
+                break;
+            }
+            let prefix = first.unwrap().trim_right_matches(':');
+            let fields = match SNMP_FIELDS.iter().find(|x| x.0 == prefix) {
+                Some(&(_, fields)) => fields,
+                None => continue,
+            };
+            for (k, v) in header.zip(values) {
+                if !fields.contains(&k) {
+                    continue;
+                }
+                let key = Key::metric(&format!("net.{}.{}", prefix, k));
+                if SNMP_INTEGERS.contains(&k) {
+                    FromStr::from_str(v).map(|x| t.add(key, Integer(x)))
+                    .ok();
+                } else {
+                    FromStr::from_str(v).map(|x| t.add(key, Counter(x)))
+                    .ok();
+                }
+            }
+        }
+        Ok(())
+    }).ok();
+    File::open(roots.proc_path("vmstat")).and_then(|f| {
+        let mut f = BufReader::new(f);
+        loop {
+            let mut line = String::with_capacity(50);
+            try!(f.read_line(&mut line));
+            if line.len() == 0 { break; }
+            let mut pieces = line.split_whitespace();
+            let name = match pieces.next() {
+                Some(x) if VMSTAT_FIELDS.contains(&x) => x,
+                _ => continue,
+            };
+            t.add_next_cnt(Key::metric(&format!("vmstat.{}", name)),
+                           &mut pieces);
+        }
+        Ok(())
+    }).ok();
+    // Pressure stall information is available since linux 4.20
+    for resource in &["cpu", "memory", "io"] {
+        File::open(roots.proc_path("pressure").join(resource))
+            .and_then(|mut f| {
+                let mut buf = String::with_capacity(200);
+                f.read_to_string(&mut buf)
+                .map(|_| buf)
+            })
+            .map(|buf| parse_pressure(t, resource, &buf))
+            .ok();
+    }
+    File::open(roots.proc_path("diskstats")).and_then(|f| {
         let mut f = BufReader::new(f);
         loop {
             let mut line = String::with_capacity(200);
@@ -247,3 +364,27 @@ pub fn read(t: &mut Tip) -> Option<u64> {
     }).ok();
     return boot_time;
 }
+
+#[cfg(test)]
+mod test {
+    use cantal::Value;
+    use history::Key;
+    use scan::Tip;
+    use super::parse_pressure;
+
+    #[test]
+    fn pressure() {
+        let mut tip = Tip::new();
+        parse_pressure(&mut tip, "io", "\
+            some avg10=1.50 avg60=0.00 avg300=0.00 total=56789\n\
+            full avg10=0.00 avg60=0.00 avg300=0.00 total=1234\n\
+            ");
+        assert_eq!(tip.map.len(), 8);
+        assert!(matches!(
+            tip.map.get(&Key::metric("pressure.io.some.avg10")),
+            Some(&Value::Float(x)) if x == 1.5));
+        assert!(matches!(
+            tip.map.get(&Key::metric("pressure.io.full.total")),
+            Some(&Value::Counter(1234))));
+    }
+}
@@ -1,16 +1,24 @@
 use std::ptr;
+use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use libc;
 
 use history::Key;
 use cantal::Value;
 use cantal::itertools::NextValue;
+use self::processes::Pid;
 
 pub mod machine;
 pub mod processes;
+pub mod groups;
 pub mod values;
 pub mod cgroups;
+pub mod cgroup_stats;
 pub mod connections;
+pub mod mountinfo;
+pub mod filesystems;
+pub mod interfaces;
+pub mod sensors;
 
 // TODO(tailhook) use some time/date crate
 
@@ -25,6 +33,53 @@ pub fn time_ms() -> u64 {
     return (tv.tv_sec as u64)*1000 +  (tv.tv_usec as u64) / 1000;
 }
 
+/// Where kernel filesystems are mounted
+///
+/// They aren't at the usual places when agent runs in a container with
+/// host's filesystems mounted somewhere else (e.g. `/host/proc`). This is
+/// also used to run collectors against fixture trees in tests.
+#[derive(Debug, Clone)]
+pub struct Roots {
+    pub proc_dir: PathBuf,
+    pub sys_dir: PathBuf,
+    pub cgroup_dir: PathBuf,
+}
+
+impl Roots {
+    pub fn new() -> Roots {
+        Roots {
+            proc_dir: PathBuf::from("/proc"),
+            sys_dir: PathBuf::from("/sys"),
+            cgroup_dir: PathBuf::from("/sys/fs/cgroup"),
+        }
+    }
+    /// Path of the file in proc, e.g. `roots.proc_path("net/tcp")`
+    pub fn proc_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.proc_dir.join(path)
+    }
+    /// Path of the file of the process, e.g. `roots.pid_path(1, "stat")`
+    pub fn pid_path<P: AsRef<Path>>(&self, pid: Pid, path: P) -> PathBuf {
+        self.proc_dir.join(pid.to_string()).join(path)
+    }
+    pub fn sys_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.sys_dir.join(path)
+    }
+    /// Path in the root filesystem of pid 1, e.g. a mount point of the host
+    pub fn host_path<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let path = path.as_ref();
+        self.pid_path(1, "root")
+            .join(path.strip_prefix("/").unwrap_or(path))
+    }
+    /// Translates mount point of cgroup filesystem, as seen in mountinfo,
+    /// to the place where it's visible for us
+    pub fn cgroup_mount(&self, mount_point: &Path) -> PathBuf {
+        match mount_point.strip_prefix("/sys/fs/cgroup") {
+            Ok(rel) => self.cgroup_dir.join(rel),
+            Err(_) => mount_point.to_path_buf(),
+        }
+    }
+}
+
 pub struct Tip {
     pub map: HashMap<Key, Value>,
 }
@@ -55,3 +110,148 @@ impl Tip {
         }
     }
 }
+
+#[cfg(test)]
+pub mod test {
+    use std::path::PathBuf;
+
+    use cantal::Value;
+    use history::Key;
+    use super::{Roots, Tip};
+    use super::{machine, cgroups, cgroup_stats, processes, interfaces};
+    use super::{connections, values};
+
+    /// Recorded `/proc` and `/sys` of a host running `nscd` with pid 42
+    pub fn fixture() -> Roots {
+        let base = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/host");
+        Roots {
+            proc_dir: base.join("proc"),
+            sys_dir: base.join("sys"),
+            cgroup_dir: base.join("sys/fs/cgroup"),
+        }
+    }
+
+    #[test]
+    fn read_machine() {
+        let mut tip = Tip::new();
+        let boot_time = machine::read(&mut tip, &fixture());
+        assert_eq!(boot_time, Some(1500000000));
+        assert!(matches!(tip.map.get(&Key::metric("uptime")),
+                         Some(&Value::Float(x)) if x == 12345.67));
+        assert!(matches!(tip.map.get(&Key::metric("cpu.user")),
+                         Some(&Value::Counter(100))));
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                    ("cpu", "1"),
+                    ("metric", "cpu.system"),
+                ])), Some(&Value::Counter(20))));
+        assert!(matches!(tip.map.get(&Key::metric("memory.MemTotal")),
+                         Some(&Value::Integer(8241152000))));
+    }
+
+    #[test]
+    fn host_path() {
+        let roots = fixture();
+        assert_eq!(roots.host_path("/mnt/data"),
+                   roots.proc_dir.join("1/root/mnt/data"));
+        assert_eq!(roots.host_path("/"), roots.proc_dir.join("1/root"));
+    }
+
+    #[test]
+    fn read_processes() {
+        let roots = fixture();
+        let cgroups = cgroups::read(&roots);
+        assert_eq!(cgroups.len(), 1);
+        assert_eq!(&cgroups[&42][..], "system.nscd");
+        let mut cache = processes::ReadCache::new();
+        let prcs = processes::read(&mut cache, &cgroups, &roots);
+        assert_eq!(prcs.len(), 1);
+        // Pid 1 only has `mountinfo` recorded
+        assert_eq!(cache.unreadable.iter().collect::<Vec<_>>(), vec![&1]);
+        let p = &prcs[0];
+        assert_eq!(p.pid, 42);
+        assert_eq!(p.name, "nscd");
+        assert_eq!(p.ppid, 1);
+        assert_eq!(p.num_threads, 3);
+        assert_eq!(p.minor_faults, 150);
+        assert_eq!(p.major_faults, 2);
+        assert_eq!(p.user_time, 30);
+        assert_eq!(p.system_time, 20);
+        assert_eq!(p.vsize, 10485760);
+        assert_eq!(p.read_bytes, 4096);
+        assert_eq!(p.swap, 8192);
+        assert_eq!(p.voluntary_ctx_switches, 100);
+        assert_eq!(p.fd_count, Some(2));
+        assert_eq!(p.fd_limit, Some(1024));
+        assert_eq!(p.oom_score, Some(5));
+        assert_eq!(p.cgroup.as_ref().map(|x| &x[..]), Some("system.nscd"));
+    }
+
+    #[test]
+    fn read_processes_cached() {
+        let roots = fixture();
+        let cgroups = cgroups::read(&roots);
+        let mut cache = processes::ReadCache::new();
+        let mut environ_cache = values::EnvironCache::new();
+        for _ in 0..3 {
+            let prcs = processes::read(&mut cache, &cgroups, &roots);
+            assert_eq!(prcs.len(), 1);
+            assert_eq!(prcs[0].cmdline, "/usr/sbin/nscd\0");
+            let environ = values::read_environ(&mut environ_cache, &prcs,
+                                               &roots);
+            assert_eq!(environ[&42].appname, None);
+        }
+        assert_eq!(cache.processes_read, 3);
+        assert_eq!(cache.cmdline_reads, 1);
+        assert_eq!(environ_cache.environ_reads, 1);
+    }
+
+    #[test]
+    fn read_cgroup_stats() {
+        let mut tip = Tip::new();
+        cgroup_stats::read(&mut tip, &fixture());
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                    ("cgroup", "system.nscd"),
+                    ("metric", "memory.current"),
+                ])), Some(&Value::Integer(2097152))));
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                    ("cgroup", "system.nscd"),
+                    ("metric", "pids.current"),
+                ])), Some(&Value::Integer(1))));
+    }
+
+    #[test]
+    fn read_interfaces() {
+        let mut tip = Tip::new();
+        let mut cache = interfaces::ReadCache::new();
+        interfaces::read(&mut tip, &mut cache, &fixture());
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                    ("interface", "eth0"),
+                    ("metric", "net.interface.speed"),
+                ])), Some(&Value::Integer(1250000000))));
+        assert!(matches!(tip.map.get(&Key::pairs(&[
+                    ("interface", "eth0"),
+                    ("metric", "net.interface.operstate"),
+                ])), Some(&Value::State((_, ref x))) if x == "up"));
+    }
+
+    #[test]
+    fn read_connections() {
+        let roots = fixture();
+        let cgroups = cgroups::read(&roots);
+        let mut cache = connections::ReadCache::new();
+        // there is no `net/unix` in fixture, which doesn't affect the rest
+        let conn = connections::read(&mut cache, &[], &cgroups, &roots)
+            .unwrap();
+        assert_eq!(conn.global.by_state.get(&connections::State::LISTEN),
+                   Some(&1));
+        assert_eq!(conn.udp.len(), 1);
+        assert_eq!(conn.udp[&53].sockets, 1);
+        assert_eq!(conn.udp[&53].drops, 4);
+        let connected = conn.udp_connected.as_ref().unwrap();
+        assert_eq!(connected.sockets, 2);
+        assert_eq!(connected.rx_queue, 0x100);
+        assert_eq!(connected.drops, 1);
+        assert_eq!(conn.unix.len(), 0);
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use super::Roots;
+
+
+#[derive(Debug)]
+pub struct Mount {
+    /// Root of the mount within the filesystem
+    pub root: String,
+    pub mount_point: PathBuf,
+    pub fstype: String,
+    pub source: String,
+    pub super_options: Vec<String>,
+}
+
+/// Decodes octal escapes (`\040` for space) used by the kernel
+fn unescape(val: &str) -> String {
+    let bytes = val.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() &&
+            bytes[i+1..i+4].iter().all(|&c| c >= b'0' && c <= b'7')
+        {
+            result.push(bytes[i+1..i+4].iter().fold(0u8,
+                |acc, &c| acc.wrapping_mul(8).wrapping_add(c - b'0')));
+            i += 4;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+/// Parses a line of `/proc/<pid>/mountinfo`
+///
+/// The line is:
+///  36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw
+/// The number of optional fields before the dash is variable.
+fn parse_line(line: &str) -> Option<Mount> {
+    let mut words = line.split(' ');
+    let root = match words.nth(3) {
+        Some(x) => unescape(x),
+        None => return None,
+    };
+    let mount_point = match words.next() {
+        Some(x) => PathBuf::from(unescape(x)),
+        None => return None,
+    };
+    let mut words = words.skip_while(|&x| x != "-").skip(1);
+    match (words.next(), words.next(), words.next()) {
+        (Some(fstype), Some(source), Some(opts)) => Some(Mount {
+            root: root,
+            mount_point: mount_point,
+            fstype: fstype.to_string(),
+            source: unescape(source),
+            super_options: opts.split(',').map(|x| x.to_string()).collect(),
+        }),
+        _ => None,
+    }
+}
+
+pub fn parse(text: &str) -> Vec<Mount> {
+    text.lines().filter_map(|line| {
+        let mount = parse_line(line);
+        if mount.is_none() {
+            debug!("Bad mountinfo line {:?}", line);
+        }
+        mount
+    }).collect()
+}
+
+/// Reads mounts of the host, i.e. of the mount namespace of pid 1
+///
+/// Mount points are relative to the root of pid 1, use `Roots::host_path`
+/// to access them, as the agent may run in a container.
+pub fn read(roots: &Roots) -> Vec<Mount> {
+    let mut buf = String::with_capacity(8192);
+    File::open(roots.pid_path(1, "mountinfo"))
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| error!("Can't read mountinfo: {}", e))
+        .map(|_| parse(&buf))
+        .unwrap_or(Vec::new())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::{parse, unescape};
+
+    #[test]
+    fn mountinfo() {
+        let mounts = parse("\
+            25 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+            26 25 0:23 / /sys/fs/cgroup rw,nosuid shared:4 \
+                - cgroup2 cgroup2 rw,nsdelegate\n\
+            27 25 0:24 / /mnt/my\\040disk rw - vfat /dev/sdb1 rw\n\
+            broken line\n\
+            ");
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(mounts[0].mount_point, Path::new("/"));
+        assert_eq!(mounts[0].fstype, "ext4");
+        assert_eq!(mounts[0].source, "/dev/sda1");
+        assert_eq!(mounts[1].mount_point, Path::new("/sys/fs/cgroup"));
+        assert_eq!(mounts[1].fstype, "cgroup2");
+        assert_eq!(mounts[1].super_options, ["rw", "nsdelegate"]);
+        assert_eq!(mounts[2].mount_point, Path::new("/mnt/my disk"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unescape("a\\040b\\134c"), "a b\\c");
+        assert_eq!(unescape("a\\0"), "a\\0");
+    }
+}
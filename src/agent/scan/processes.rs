@@ -1,21 +1,69 @@
+use std::mem;
+use std::thread;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::io::{Read, BufReader, BufRead};
+use std::io::Read;
 use std::str::from_utf8;
-use std::fs::{File, read_dir};
-use std::collections::HashMap;
+use std::fs::{File, read_dir, read_link};
+use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
 
 use libc;
 
 use cantal::itertools::{NextValue, NextStr};
-use super::Tip;
+use super::{Tip, Roots};
 use history::Key;
 use scan::cgroups::CGroups;
 
 pub type Pid = u32;
 
+/// Minimum number of processes to read in parallel, for fewer processes
+/// starting threads costs more than reading
+const MIN_PARALLEL: usize = 1000;
+
+/// Data of the process which is read only once
+///
+/// It's kept while pid, start time and name of the process are the same.
+/// Name is compared because it changes on exec, but pid and start time
+/// don't. Still, if the process rewrites its command-line, the change is
+/// not noticed.
+///
+/// It's shared by the cache and the reading threads, so it's not copied
+/// on every scan.
+struct Known {
+    start_time: u64,
+    name: String,
+    cmdline: String,
+    exe: Option<PathBuf>,
+}
+
+struct Task {
+    pid: Pid,
+    cgroup: Option<Arc<String>>,
+    known: Option<Arc<Known>>,
+}
+
+/// Process, its data to keep for the next scan, and whether the data was
+/// read on this scan
+type TaskResult = Result<(MinimalProcess, Arc<Known>, bool), ()>;
+
 pub struct ReadCache {
     tick: u32,
+    /// Number of threads to read processes in, processes are read in the
+    /// calling thread if it's `1`
+    pub threads: usize,
+    known: HashMap<Pid, Arc<Known>>,
+    /// Pids which are still in `/proc` but were failed to read on the last
+    /// scan, so the processes may be still running
+    pub unreadable: HashSet<Pid>,
+    /// Number of new processes, i.e. processes which `cmdline` and `exe`
+    /// are read for, since start
+    pub cmdline_reads: u64,
+    /// Number of processes read since start
+    pub processes_read: u64,
+    /// Number of processes failed to read, usually those which have exited
+    /// while being read
+    pub errors: u64,
 }
 
 #[derive(RustcEncodable)]
@@ -37,7 +85,17 @@ pub struct MinimalProcess {
     pub cmdline: String,
     pub read_bytes: u64,
     pub write_bytes: u64,
+    pub minor_faults: u64,
+    pub major_faults: u64,
+    pub voluntary_ctx_switches: u64,
+    pub involuntary_ctx_switches: u64,
+    pub swap: u64,
+    pub fd_count: Option<u32>,
+    pub fd_limit: Option<u64>,
+    pub oom_score: Option<u32>,
     pub cgroup: Option<Arc<String>>,
+    /// `None` if we have no permissions to read the link
+    pub exe: Option<PathBuf>,
 }
 
 fn page_size() -> usize {
@@ -45,9 +103,9 @@ fn page_size() -> usize {
     return 4096;
 }
 
-fn parse_io(pid: Pid) -> Result<(u64, u64), ()> {
+fn parse_io(roots: &Roots, pid: Pid) -> Result<(u64, u64), ()> {
     let mut buf = String::with_capacity(512);
-    try!(File::open(&format!("/proc/{}/io", pid))
+    try!(File::open(roots.pid_path(pid, "io"))
         .and_then(|mut f| f.read_to_string(&mut buf))
         .map_err(|e| debug!("Can't read io file: {}", e)));
     let mut read_bytes = None;
@@ -68,14 +126,34 @@ fn parse_io(pid: Pid) -> Result<(u64, u64), ()> {
     Ok((read_bytes, write_bytes))
 }
 
-fn parse_status(pid: Pid) -> Result<(u32, u32), ()> {
-    let buf = BufReader::new(try!(File::open(&format!("/proc/{}/status", pid))
-        .map_err(|e| debug!("Can't read io file: {}", e))));
+struct Status {
+    uid: u32,
+    gid: u32,
+    voluntary_ctx_switches: u64,
+    involuntary_ctx_switches: u64,
+    swap: u64,
+}
+
+fn parse_status(roots: &Roots, pid: Pid) -> Result<Status, ()> {
+    let mut buf = String::with_capacity(2048);
+    try!(File::open(roots.pid_path(pid, "status"))
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| debug!("Can't read status file: {}", e)));
+    parse_status_text(&buf)
+        .map_err(|_| error!("Can't parse /proc/{}/status", pid))
+}
+
+fn parse_status_text(buf: &str) -> Result<Status, ()> {
     let mut uid = None;
     let mut gid = None;
+    let mut status = Status {
+        uid: 0,
+        gid: 0,
+        voluntary_ctx_switches: 0,
+        involuntary_ctx_switches: 0,
+        swap: 0,
+    };
     for line in buf.lines() {
-        let line = try!(line
-            .map_err(|e| debug!("Can't read io file: {}", e)));
         let mut pair = line.split(':');
         match (pair.next(), pair.next()) {
             (Some("Uid"), Some(v)) => {
@@ -92,31 +170,87 @@ fn parse_status(pid: Pid) -> Result<(u32, u32), ()> {
                 gid = v.split_whitespace().next()
                     .and_then(|x| x.parse().ok());
             }
+            // Kernel threads have no memory-related fields
+            (Some("VmSwap"), Some(v)) => {
+                // The line is:
+                //  VmSwap:      128 kB
+                status.swap = v.split_whitespace().next()
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .map(|x| x * 1024)
+                    .unwrap_or(0);
+            }
+            (Some("voluntary_ctxt_switches"), Some(v)) => {
+                status.voluntary_ctx_switches = v.trim().parse()
+                    .unwrap_or(0);
+            }
+            (Some("nonvoluntary_ctxt_switches"), Some(v)) => {
+                status.involuntary_ctx_switches = v.trim().parse()
+                    .unwrap_or(0);
+            }
             _ => {}
         }
-        if uid.is_some() && gid.is_some() { break; }
     }
-    let uid = try!(uid.ok_or(())
-        .map_err(|_| error!("Can't parse /proc/{}/status", pid)));
-    let gid = try!(gid.ok_or(())
-        .map_err(|_| error!("Can't parse /proc/{}/status", pid)));
-    Ok((uid, gid))
+    status.uid = try!(uid.ok_or(()));
+    status.gid = try!(gid.ok_or(()));
+    Ok(status)
 }
 
-fn read_process(cache: &mut ReadCache, cgroup: Option<&Arc<String>>, pid: Pid)
-    -> Result<MinimalProcess, ()>
-{
-    let cmdline = {
-        let mut buf = [0u8; 4096];
-        let bytes = try!(File::open(&format!("/proc/{}/cmdline", pid))
-            .and_then(|mut f| f.read(&mut buf))
-            .map_err(|_| debug!("Can't read cmdline file")));
-        // Command-line may be non-full, but we don't care
-        String::from_utf8_lossy(&buf[..bytes]).to_string()
-    };
+/// Returns soft limit of open files, `None` means unlimited
+fn parse_limits(buf: &str) -> Option<u64> {
+    for line in buf.lines() {
+        // The line is:
+        //  Max open files            1024                 4096      files
+        if line.starts_with("Max open files") {
+            return line["Max open files".len()..].split_whitespace().next()
+                .and_then(|x| x.parse().ok());
+        }
+    }
+    return None;
+}
+
+fn read_fd_limit(roots: &Roots, pid: Pid) -> Option<u64> {
+    let mut buf = String::with_capacity(2048);
+    File::open(roots.pid_path(pid, "limits"))
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| debug!("Can't read limits file: {}", e))
+        .ok()
+        .and_then(|_| parse_limits(&buf))
+}
+
+/// Number of open file descriptors
+///
+/// Returns `None` if we have no permissions to look into the directory,
+/// which is the case for processes of other users unless we are root
+fn read_fd_count(roots: &Roots, pid: Pid) -> Option<u32> {
+    read_dir(roots.pid_path(pid, "fd"))
+        .map_err(|e| debug!("Can't read fd dir: {}", e))
+        .ok()
+        .map(|dir| dir.count() as u32)
+}
+
+fn read_oom_score(roots: &Roots, pid: Pid) -> Option<u32> {
+    let mut buf = String::with_capacity(16);
+    File::open(roots.pid_path(pid, "oom_score"))
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| debug!("Can't read oom_score file: {}", e))
+        .ok()
+        .and_then(|_| buf.trim().parse().ok())
+}
 
+fn read_cmdline(roots: &Roots, pid: Pid) -> Result<String, ()> {
+    let mut buf = [0u8; 4096];
+    let bytes = try!(File::open(roots.pid_path(pid, "cmdline"))
+        .and_then(|mut f| f.read(&mut buf))
+        .map_err(|_| debug!("Can't read cmdline file")));
+    // Command-line may be non-full, but we don't care
+    Ok(String::from_utf8_lossy(&buf[..bytes]).to_string())
+}
+
+/// Reads the process, returns whether command-line was read too
+fn read_process(tick: u32, roots: &Roots, task: &Task) -> TaskResult {
+    let pid = task.pid;
     let mut buf = [0u8; 2048];
-    let bytes = try!(File::open(&format!("/proc/{}/stat", pid))
+    let bytes = try!(File::open(roots.pid_path(pid, "stat"))
         .and_then(|mut f| f.read(&mut buf))
         .map_err(|e| debug!("Can't read stat file: {}", e)));
     if bytes == 2048 {
@@ -137,56 +271,171 @@ fn read_process(cache: &mut ReadCache, cgroup: Option<&Arc<String>>, pid: Pid)
         .map_err(|e| debug!("Can't decode stat file: {}", e)));
     let mut words = stat_line.split_whitespace();
 
-    let (read_bytes, write_bytes) = try!(parse_io(pid));
-    let (uid, gid) = try!(parse_status(pid));
+    let state = try!(words.next_str()).chars().next().unwrap_or('-');
+    let ppid = try!(words.next_value());
+    let minor_faults = try!(words.nth_value(5));
+    let major_faults = try!(words.nth_value(1));
+    let user_time = try!(words.nth_value(1));
+    let system_time = try!(words.next_value());
+    let child_user_time = try!(words.next_value());
+    let child_system_time = try!(words.next_value());
+    let num_threads = try!(words.nth_value(2));
+    let start_time = {
+        let stime: u64 = try!(words.nth_value(1));
+        (stime * 1000) / tick as u64 };
+    let vsize = try!(words.next_value());
+    let rss = {
+        let rss: u64 = try!(words.next_value());
+        rss * page_size() as u64};
+
+    let (known, cmdline_read) = match task.known {
+        Some(ref known)
+        if known.start_time == start_time && known.name == name
+        => (known.clone(), false),
+        _ => (Arc::new(Known {
+            start_time: start_time,
+            name: name.clone(),
+            cmdline: try!(read_cmdline(roots, pid)),
+            exe: read_link(roots.pid_path(pid, "exe"))
+                .map_err(|e| debug!("Can't read exe link: {}", e))
+                .ok(),
+        }), true),
+    };
+    let (read_bytes, write_bytes) = try!(parse_io(roots, pid));
+    let status = try!(parse_status(roots, pid));
 
-    return Ok(MinimalProcess {
+    return Ok((MinimalProcess {
         pid: pid,
-        uid: uid,
-        gid: gid,
+        uid: status.uid,
+        gid: status.gid,
         name: name,
-        state: try!(words.next_str()).chars().next().unwrap_or('-'),
-        ppid: try!(words.next_value()),
-        user_time: try!(words.nth_value(9)),
-        system_time: try!(words.next_value()),
-        child_user_time: try!(words.next_value()),
-        child_system_time: try!(words.next_value()),
-        num_threads: try!(words.nth_value(2)),
-        start_time: {
-            let stime: u64 = try!(words.nth_value(1));
-            (stime * 1000) / cache.tick as u64 },
-        vsize: try!(words.next_value()),
-        rss: {
-            let rss: u64 = try!(words.next_value());
-            rss * page_size() as u64},
-        cmdline: cmdline,
+        state: state,
+        ppid: ppid,
+        minor_faults: minor_faults,
+        major_faults: major_faults,
+        user_time: user_time,
+        system_time: system_time,
+        child_user_time: child_user_time,
+        child_system_time: child_system_time,
+        num_threads: num_threads,
+        start_time: start_time,
+        vsize: vsize,
+        rss: rss,
+        cmdline: known.cmdline.clone(),
         read_bytes: read_bytes,
         write_bytes: write_bytes,
-        cgroup: cgroup.map(|x| x.clone()),
-    });
+        voluntary_ctx_switches: status.voluntary_ctx_switches,
+        involuntary_ctx_switches: status.involuntary_ctx_switches,
+        swap: status.swap,
+        fd_count: read_fd_count(roots, pid),
+        fd_limit: read_fd_limit(roots, pid),
+        oom_score: read_oom_score(roots, pid),
+        cgroup: task.cgroup.clone(),
+        exe: known.exe.clone(),
+    }, known, cmdline_read));
 }
 
-pub fn read(cache: &mut ReadCache, cgroups: &HashMap<Pid, Arc<String>>)
-    -> Vec<MinimalProcess>
+fn read_tasks(tick: u32, roots: &Roots, tasks: &[Task]) -> Vec<TaskResult> {
+    tasks.iter().map(|task| read_process(tick, roots, task)).collect()
+}
+
+/// Splits tasks into a chunk per thread, results are in the same order
+fn read_parallel(tick: u32, roots: &Roots, threads: usize,
+    mut tasks: Vec<Task>)
+    -> Vec<TaskResult>
 {
-    read_dir("/proc")
-    .map_err(|e| error!("Error listing /proc: {}", e))
+    let chunk = (tasks.len() + threads - 1) / threads;
+    let mut handles = Vec::with_capacity(threads);
+    while tasks.len() > 0 {
+        let rest = tasks.split_off(if chunk < tasks.len() {
+            chunk
+        } else {
+            tasks.len()
+        });
+        let part = mem::replace(&mut tasks, rest);
+        let roots = roots.clone();
+        handles.push(thread::spawn(move || {
+            read_tasks(tick, &roots, &part)
+        }));
+    }
+    let mut result = Vec::new();
+    for handle in handles {
+        match handle.join() {
+            Ok(part) => result.extend(part),
+            // Processes of the chunk are skipped for this scan
+            Err(_) => error!("Process reading thread panicked"),
+        }
+    }
+    return result;
+}
+
+fn list_pids(roots: &Roots) -> Vec<Pid> {
+    read_dir(&roots.proc_dir)
+    .map_err(|e| error!("Error listing {:?}: {}", roots.proc_dir, e))
     .map(|lst| lst
         .filter_map(|x| x.ok())
         .filter_map(|x| x.path().file_name()
                          .and_then(|x| x.to_str())
                          .and_then(|x| FromStr::from_str(x).ok()))
-        .filter_map(|x| read_process(cache, cgroups.get(&x), x).ok())
         .collect())
     .unwrap_or(Vec::new())
 }
 
+/// Reads all processes
+///
+/// Command-lines and executables of the processes seen on previous scan
+/// are not read again. If `cache.threads` is more than one, and there are
+/// lots of processes, they are read in parallel.
+pub fn read(cache: &mut ReadCache, cgroups: &HashMap<Pid, Arc<String>>,
+    roots: &Roots)
+    -> Vec<MinimalProcess>
+{
+    let pids = list_pids(roots);
+    let tasks = pids.iter().map(|&pid| Task {
+        pid: pid,
+        cgroup: cgroups.get(&pid).cloned(),
+        known: cache.known.get(&pid).cloned(),
+    }).collect::<Vec<_>>();
+    let results = if cache.threads > 1 && tasks.len() >= MIN_PARALLEL {
+        read_parallel(cache.tick, roots, cache.threads, tasks)
+    } else {
+        read_tasks(cache.tick, roots, &tasks)
+    };
+    let mut processes = Vec::with_capacity(results.len());
+    let mut known = HashMap::with_capacity(results.len());
+    for result in results {
+        match result {
+            Ok((prc, prc_known, cmdline_read)) => {
+                cache.processes_read += 1;
+                if cmdline_read {
+                    cache.cmdline_reads += 1;
+                }
+                known.insert(prc.pid, prc_known);
+                processes.push(prc);
+            }
+            Err(()) => cache.errors += 1,
+        }
+    }
+    cache.unreadable = pids.into_iter()
+        .filter(|pid| !known.contains_key(pid))
+        .collect();
+    // Only processes alive are kept, so pid reuse is not an issue
+    cache.known = known;
+    return processes;
+}
+
 impl ReadCache {
     pub fn new() -> ReadCache {
         ReadCache {
             tick: unsafe {
                 libc::sysconf(libc::_SC_CLK_TCK) as u32
             },
+            threads: 1,
+            known: HashMap::new(),
+            unreadable: HashSet::new(),
+            cmdline_reads: 0,
+            processes_read: 0,
+            errors: 0,
         }
     }
 }
@@ -206,8 +455,8 @@ fn key(metric: &str, pid: &str, cgroup: Option<&str>) -> Key {
     }
 }
 
-pub fn write_tip(tip: &mut Tip, processes: &Vec<MinimalProcess>,
-    cgroups: &CGroups)
+pub fn write_tip<'x, I>(tip: &mut Tip, processes: I, cgroups: &CGroups)
+    where I: IntoIterator<Item=&'x MinimalProcess>
 {
     use cantal::Value::*;
     for p in processes {
@@ -227,6 +476,135 @@ pub fn write_tip(tip: &mut Tip, processes: &Vec<MinimalProcess>,
             Counter(p.read_bytes));
         tip.add(key("write_bytes", &pid, cgroup),
             Counter(p.write_bytes));
-        // TODO(tailhook) FDSize
+        tip.add(key("minor_faults", &pid, cgroup),
+            Counter(p.minor_faults));
+        tip.add(key("major_faults", &pid, cgroup),
+            Counter(p.major_faults));
+        tip.add(key("voluntary_ctx_switches", &pid, cgroup),
+            Counter(p.voluntary_ctx_switches));
+        tip.add(key("involuntary_ctx_switches", &pid, cgroup),
+            Counter(p.involuntary_ctx_switches));
+        tip.add(key("swap", &pid, cgroup),
+            Integer(p.swap as i64));
+        if let Some(fds) = p.fd_count {
+            tip.add(key("fds", &pid, cgroup), Integer(fds as i64));
+        }
+        if let Some(limit) = p.fd_limit {
+            tip.add(key("fd_limit", &pid, cgroup), Integer(limit as i64));
+        }
+        if let Some(score) = p.oom_score {
+            tip.add(key("oom_score", &pid, cgroup), Integer(score as i64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use scan::Roots;
+    use super::{parse_status_text, parse_limits, read_parallel, Task};
+
+    #[test]
+    fn status() {
+        let status = parse_status_text("\
+            Name:\tbash\n\
+            Uid:\t1000\t1000\t1000\t1000\n\
+            Gid:\t100\t100\t100\t100\n\
+            FDSize:\t256\n\
+            VmSwap:\t     128 kB\n\
+            voluntary_ctxt_switches:\t150\n\
+            nonvoluntary_ctxt_switches:\t3\n\
+            ").unwrap();
+        assert_eq!(status.uid, 1000);
+        assert_eq!(status.gid, 100);
+        assert_eq!(status.swap, 131072);
+        assert_eq!(status.voluntary_ctx_switches, 150);
+        assert_eq!(status.involuntary_ctx_switches, 3);
+        assert!(parse_status_text("Name:\tbash\n").is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let text = "\
+            Limit                Soft Limit   Hard Limit   Units\n\
+            Max processes        63422        63422        processes\n\
+            Max open files       1024         4096         files\n\
+            ";
+        assert_eq!(parse_limits(text), Some(1024));
+        assert_eq!(parse_limits("Max open files  unlimited  unlimited  files"),
+                   None);
+    }
+
+    fn tasks(pids: &[u32]) -> Vec<Task> {
+        pids.iter().map(|&pid| Task {
+            pid: pid,
+            cgroup: None,
+            known: None,
+        }).collect()
+    }
+
+    #[test]
+    fn parallel() {
+        let roots = Roots {
+            proc_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/fixtures/host/proc"),
+            .. Roots::new()
+        };
+        let result = read_parallel(100, &roots, 2, tasks(&[42, 7, 42]));
+        assert_eq!(result.len(), 3);
+        assert!(matches!(result[0], Ok((ref p, _, true)) if p.pid == 42));
+        assert!(result[1].is_err());
+        assert!(matches!(result[2], Ok((ref p, _, true)) if p.pid == 42));
+    }
+}
+
+#[cfg(all(test, feature="nightly"))]
+mod bench {
+    use std::collections::HashMap;
+
+    use test::Bencher;
+    use scan::Roots;
+    use scan::values::{EnvironCache, read_environ};
+    use super::{ReadCache, read};
+
+    /// Processes of this machine, the way they were read before caching:
+    /// command-line and environment of every process are read on every
+    /// scan, in the calling thread. The only difference is that `exe` link
+    /// is read too, which is cheap compared to the files.
+    #[bench]
+    fn uncached(b: &mut Bencher) {
+        let roots = Roots::new();
+        let cgroups = HashMap::new();
+        b.iter(|| {
+            let prcs = read(&mut ReadCache::new(), &cgroups, &roots);
+            read_environ(&mut EnvironCache::new(), &prcs, &roots);
+        });
+    }
+
+    fn cached(b: &mut Bencher, threads: usize) {
+        let roots = Roots::new();
+        let cgroups = HashMap::new();
+        let mut cache = ReadCache::new();
+        let mut environ = EnvironCache::new();
+        cache.threads = threads;
+        let prcs = read(&mut cache, &cgroups, &roots);
+        read_environ(&mut environ, &prcs, &roots);
+        b.iter(|| {
+            let prcs = read(&mut cache, &cgroups, &roots);
+            read_environ(&mut environ, &prcs, &roots);
+        });
+    }
+
+    #[bench]
+    fn cached_sequential(b: &mut Bencher) {
+        cached(b, 1);
+    }
+
+    /// Threads are only used if there are at least `MIN_PARALLEL`
+    /// processes, otherwise it's the same as `cached_sequential`
+    #[bench]
+    fn cached_4_threads(b: &mut Bencher) {
+        cached(b, 4);
     }
 }
@@ -0,0 +1,181 @@
+use std::fs::{File, read_dir};
+use std::io::Read;
+use std::path::Path;
+
+use cantal::Value::{Float, Integer};
+use history::Key;
+use super::{Tip, Roots};
+
+
+fn read_file(path: &Path) -> Option<String> {
+    let mut buf = String::with_capacity(64);
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .ok()
+        .map(|_| buf.trim().to_string())
+}
+
+fn key(chip: &str, label: &str, metric: &str) -> Key {
+    Key::pairs(&[
+        ("chip", chip),
+        ("label", label),
+        ("metric", metric),
+    ])
+}
+
+/// Key of a hwmon sensor, `hwmon` is the name of the `hwmonN` directory,
+/// because several chips may have the same name (e.g. one `coretemp` per
+/// CPU package)
+fn hwmon_key(chip: &str, hwmon: &str, label: &str, metric: &str) -> Key {
+    Key::pairs(&[
+        ("chip", chip),
+        ("hwmon", hwmon),
+        ("label", label),
+        ("metric", metric),
+    ])
+}
+
+/// Reads a `<kind><N>_input` sensor of hwmon chip
+///
+/// Temperatures are in millidegrees Celsius, voltages are in millivolts,
+/// fans are in RPM.
+fn read_sensor(t: &mut Tip, dir: &Path, chip: &str, hwmon: &str,
+    sensor: &str)
+{
+    let value = match read_file(&dir.join(format!("{}_input", sensor)))
+        .and_then(|x| x.parse::<i64>().ok())
+    {
+        Some(x) => x,
+        // Disconnected sensors return errors on read
+        None => return,
+    };
+    let label = read_file(&dir.join(format!("{}_label", sensor)))
+        .unwrap_or(sensor.to_string());
+    if sensor.starts_with("temp") {
+        t.add(hwmon_key(chip, hwmon, &label, "hwmon.temperature"),
+            Float(value as f64 / 1000.));
+    } else if sensor.starts_with("in") {
+        t.add(hwmon_key(chip, hwmon, &label, "hwmon.voltage"),
+            Float(value as f64 / 1000.));
+    } else if sensor.starts_with("fan") {
+        t.add(hwmon_key(chip, hwmon, &label, "hwmon.fan"),
+            Integer(value));
+    }
+}
+
+fn read_hwmon(t: &mut Tip, root: &Path) {
+    let dir = match read_dir(root) {
+        Ok(dir) => dir,
+        Err(e) => {
+            debug!("Can't read {:?}: {}", root, e);
+            return;
+        }
+    };
+    for chip_dir in dir.filter_map(|x| x.ok()).map(|x| x.path()) {
+        let hwmon = match chip_dir.file_name().and_then(|x| x.to_str()) {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+        // Older kernels have sensor files in the `device` subdirectory
+        let chip_dir = if chip_dir.join("name").exists() {
+            chip_dir
+        } else {
+            chip_dir.join("device")
+        };
+        let chip = match read_file(&chip_dir.join("name")) {
+            Some(x) => x,
+            None => continue,
+        };
+        let files = match read_dir(&chip_dir) {
+            Ok(files) => files,
+            Err(_) => continue,
+        };
+        for entry in files.filter_map(|x| x.ok()) {
+            let fname = entry.file_name();
+            let fname = match fname.to_str() {
+                Some(x) => x,
+                None => continue,
+            };
+            if !fname.ends_with("_input") {
+                continue;
+            }
+            let sensor = &fname[..fname.len() - "_input".len()];
+            read_sensor(t, &chip_dir, &chip, &hwmon, sensor);
+        }
+    }
+}
+
+fn read_thermal(t: &mut Tip, root: &Path) {
+    let dir = match read_dir(root) {
+        Ok(dir) => dir,
+        Err(e) => {
+            debug!("Can't read {:?}: {}", root, e);
+            return;
+        }
+    };
+    for entry in dir.filter_map(|x| x.ok()) {
+        let zone = entry.file_name();
+        let zone = match zone.to_str() {
+            Some(x) if x.starts_with("thermal_zone") => x,
+            _ => continue,
+        };
+        let path = entry.path();
+        let typ = read_file(&path.join("type"))
+            .unwrap_or_else(|| zone.to_string());
+        if let Some(temp) = read_file(&path.join("temp"))
+            .and_then(|x| x.parse::<i64>().ok())
+        {
+            t.add(key(&typ, zone, "thermal.temperature"),
+                Float(temp as f64 / 1000.));
+        }
+    }
+}
+
+/// Reads temperatures, voltages and fan speeds
+///
+/// Keys are `chip` (as reported by the driver), `hwmon` (the `hwmonN`
+/// directory) and `label` of the sensor, the latter being `tempN`, `inN`,
+/// `fanN` if there is no label. For thermal zones `chip` is the type of
+/// the zone and there is no `hwmon`. Does nothing on machines
+/// without sensors, like most virtual ones.
+pub fn read(t: &mut Tip, roots: &Roots) {
+    read_hwmon(t, &roots.sys_path("class/hwmon"));
+    read_thermal(t, &roots.sys_path("class/thermal"));
+}
+
+#[cfg(test)]
+mod test {
+    use cantal::Value;
+    use scan::Tip;
+    use scan::test::fixture;
+    use super::{read_hwmon, read_thermal, key, hwmon_key};
+
+    #[test]
+    fn hwmon() {
+        let roots = fixture();
+        let mut tip = Tip::new();
+        read_hwmon(&mut tip, &roots.sys_path("class/hwmon"));
+        read_thermal(&mut tip, &roots.sys_path("class/thermal"));
+        read_hwmon(&mut tip, &roots.sys_path("class/nonexistent"));
+        assert_eq!(tip.map.len(), 6);
+        assert!(matches!(tip.map.get(&hwmon_key("coretemp", "hwmon0",
+                                     "Package id 0", "hwmon.temperature")),
+                         Some(&Value::Float(x)) if x == 45.));
+        assert!(matches!(tip.map.get(&hwmon_key("coretemp", "hwmon0",
+                                     "temp2", "hwmon.temperature")),
+                         Some(&Value::Float(x)) if x == 41.5));
+        // Second chip with the same name and sensor doesn't overwrite first
+        assert!(matches!(tip.map.get(&hwmon_key("coretemp", "hwmon1",
+                                     "temp2", "hwmon.temperature")),
+                         Some(&Value::Float(x)) if x == 52.));
+        assert!(matches!(tip.map.get(&hwmon_key("coretemp", "hwmon0",
+                                     "in0", "hwmon.voltage")),
+                         Some(&Value::Float(x)) if x == 1.2));
+        assert!(matches!(tip.map.get(&hwmon_key("coretemp", "hwmon0",
+                                     "fan1", "hwmon.fan")),
+                         Some(&Value::Integer(2100))));
+        assert!(matches!(tip.map.get(&key("x86_pkg_temp", "thermal_zone0",
+                                          "thermal.temperature")),
+                         Some(&Value::Float(x)) if x == 47.));
+    }
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::io::{BufReader, BufRead};
 use std::fs::{File};
 use std::ffi::{OsStr, OsString};
@@ -6,24 +6,83 @@ use std::str;
 use std::ascii::AsciiExt;
 use std::os::unix::prelude::OsStrExt;
 use std::path::{Path, PathBuf};
-use std::collections::{HashMap};
+use std::collections::{HashMap, BTreeMap};
 
 use cantal::{Metadata, Value, Descriptor};
 use rustc_serialize::json::Json;
+use quire::validate::{Structure, Sequence, Mapping, Scalar, Directory};
+use scan_dir::ScanDir;
 
-use super::Tip;
+use super::{Tip, Roots};
 use super::super::util::tree_collect;
 use history::Key;
 use super::processes::{Pid, MinimalProcess};
 use scan::cgroups::CGroups;
 
 
+/// Variables of the environment we are interested in
+///
+/// Environment can't change without exec, so it's read once per process,
+/// the same way as command-line is (see `processes::Known`).
+pub struct Environ {
+    start_time: u64,
+    name: String,
+    /// Value of `CANTAL_APPNAME` environment variable
+    pub appname: Option<String>,
+    /// Value of `CANTAL_PATH` environment variable
+    pub path: Option<PathBuf>,
+}
+
+/// Environment of the running processes by pid
+pub type Environs = Arc<HashMap<Pid, Arc<Environ>>>;
+
+pub struct EnvironCache {
+    environ: Environs,
+    /// Number of `environ` files read since start
+    pub environ_reads: u64,
+}
+
 pub struct ReadCache {
     metadata: HashMap<PathBuf, Metadata>,
+    /// Number of `.values` files read since start
+    pub files_read: u64,
+    /// Number of `.meta` files read since start
+    pub metadata_reads: u64,
+    pub errors: u64,
+}
+
+/// Statically configured files, read from `*.metrics.yaml`
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Config {
+    /// Base paths of values (without `.meta` and `.values` suffix)
+    pub files: Vec<Source>,
+    /// Directories where every `*.values` file is read, `*` and `?`
+    /// wildcards may be used in any component of the path
+    pub directories: Vec<Source>,
+}
+
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Source {
+    pub path: PathBuf,
+    pub labels: BTreeMap<String, String>,
+}
+
+fn source_validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("path", Directory::new().is_absolute(true))
+    .member("labels", Mapping::new(Scalar::new(), Scalar::new()))
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("files", Sequence::new(source_validator()))
+    .member("directories", Sequence::new(source_validator()))
 }
 
-fn get_env_vars(pid: u32) -> (Option<String>, Option<PathBuf>) {
-    let file = match File::open(&format!("/proc/{}/environ", pid)) {
+fn get_env_vars(roots: &Roots, pid: u32)
+    -> (Option<String>, Option<PathBuf>)
+{
+    let file = match File::open(roots.pid_path(pid, "environ")) {
         Ok(file) => file,
         Err(e) => {
             debug!("Can't read environ file: {}", e);
@@ -71,6 +130,43 @@ fn get_env_vars(pid: u32) -> (Option<String>, Option<PathBuf>) {
     return (name, path);
 }
 
+/// Reads environment of the processes which weren't seen on previous scan
+///
+/// Environment is used both by process groups and by `read`, so it's read
+/// by the processes collector, and is shared with others.
+pub fn read_environ(cache: &mut EnvironCache, processes: &[MinimalProcess],
+    roots: &Roots)
+    -> Environs
+{
+    let mut environ = HashMap::with_capacity(processes.len());
+    for prc in processes.iter() {
+        let cached = cache.environ.get(&prc.pid).and_then(|env| {
+            if env.start_time == prc.start_time && env.name == prc.name {
+                Some(env.clone())
+            } else {
+                None
+            }
+        });
+        let env = match cached {
+            Some(env) => env,
+            None => {
+                cache.environ_reads += 1;
+                let (appname, path) = get_env_vars(roots, prc.pid);
+                Arc::new(Environ {
+                    start_time: prc.start_time,
+                    name: prc.name.clone(),
+                    appname: appname,
+                    path: path,
+                })
+            }
+        };
+        environ.insert(prc.pid, env);
+    }
+    // Only processes alive are kept, so pid reuse is not an issue
+    cache.environ = Arc::new(environ);
+    return cache.environ.clone();
+}
+
 fn relative_from(path: &Path, prefix: &Path) -> PathBuf {
     let mut pref_iter = prefix.components();
     let mut path_iter = path.components();
@@ -83,6 +179,58 @@ fn relative_from(path: &Path, prefix: &Path) -> PathBuf {
     }
 }
 
+/// Matches a file name against a pattern with `*` and `?` wildcards
+fn wildcard_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(&'*'), _) => {
+            wildcard_match(&pattern[1..], name) ||
+            !name.is_empty() && wildcard_match(pattern, &name[1..])
+        }
+        (Some(&'?'), Some(_)) => wildcard_match(&pattern[1..], &name[1..]),
+        (Some(a), Some(b)) if a == b => {
+            wildcard_match(&pattern[1..], &name[1..])
+        }
+        _ => false,
+    }
+}
+
+/// Returns directories matching the path with wildcards, sorted
+///
+/// Like in shell, wildcards don't match hidden directories.
+fn expand_dirs(path: &Path) -> Vec<PathBuf> {
+    let mut result = vec![PathBuf::new()];
+    for cmp in path.components() {
+        let part = cmp.as_os_str();
+        let pattern = match part.to_str() {
+            Some(x) if x.contains('*') || x.contains('?') => {
+                x.chars().collect::<Vec<_>>()
+            }
+            _ => {
+                for dir in &mut result {
+                    dir.push(part);
+                }
+                continue;
+            }
+        };
+        let mut matched = Vec::new();
+        for dir in &result {
+            ScanDir::dirs().read(dir, |iter| {
+                for (entry, name) in iter {
+                    let name = name.chars().collect::<Vec<_>>();
+                    if wildcard_match(&pattern, &name) {
+                        matched.push(entry.path());
+                    }
+                }
+            }).map_err(|e| debug!("Error reading dir {:?}: {}", dir, e))
+            .ok();
+        }
+        matched.sort();
+        result = matched;
+    }
+    return result;
+}
+
 fn add_suffix<P: AsRef<Path>, E: AsRef<OsStr>>(path: P, ext: E) -> PathBuf
 {
     let result: &Path = path.as_ref();
@@ -92,14 +240,16 @@ fn add_suffix<P: AsRef<Path>, E: AsRef<OsStr>>(path: P, ext: E) -> PathBuf
     result.with_file_name(name)
 }
 
-fn read_values(cache: &ReadCache, path: &PathBuf)
-    -> (Option<Vec<(Rc<Descriptor>, Value)>>, Option<Metadata>)
+fn read_values(cache: &mut ReadCache, path: &PathBuf)
+    -> (Option<Vec<(Arc<Descriptor>, Value)>>, Option<Metadata>)
 {
     let mpath = add_suffix(path, ".meta");
     if let Some(meta) = cache.metadata.get(path) {
         let data = meta.read_data(&add_suffix(path, ".values"));
+        cache.files_read += 1;
         if let Err(ref e) = data {
             debug!("Error reading {:?}: {}", mpath, e);
+            cache.errors += 1;
         }
         // TODO(tailhook) check mtime of metadata
         if meta.still_fresh(&mpath) {
@@ -108,20 +258,27 @@ fn read_values(cache: &ReadCache, path: &PathBuf)
     }
     for _ in 0..1 {
         let mres = Metadata::read(&mpath);
+        cache.metadata_reads += 1;
         if let Ok(meta) = mres {
             debug!("Read new metadata {:?}", path);
             let data = meta.read_data(&add_suffix(path, ".values"));
+            cache.files_read += 1;
             if !meta.still_fresh(&mpath) {
                 continue;
             }
+            if data.is_err() {
+                cache.errors += 1;
+            }
             return (data.ok(), Some(meta));
         } else {
             let err = mres.err().unwrap();
             info!("Error reading metadata {:?}: {}", mpath, err);
+            cache.errors += 1;
             return (None, None);
         }
     }
     warn!("Constantly changing metadata {:?}", mpath);
+    cache.errors += 1;
     return (None, None);
 }
 
@@ -146,20 +303,25 @@ fn key(pid: &str, cgroup: Option<&str>, name_opt: &Option<String>, json: &Json)
 }
 
 pub fn read(tip: &mut Tip, cache: &mut ReadCache, processes: &[MinimalProcess],
-    cgroups: &CGroups)
+    environ: &Environs, cgroups: &CGroups, roots: &Roots)
 {
     for prc in processes.iter() {
-        let (name_opt, path_opt) = get_env_vars(prc.pid);
-        if let Some(path) = path_opt {
+        let env = match environ.get(&prc.pid) {
+            Some(env) => env,
+            None => continue,
+        };
+        if let Some(ref path) = env.path {
             let pid = prc.pid.to_string();
             let cgroup = cgroups.get(&prc.pid).map(|x| &x[..]);
             // TODO(tailhook) check if not already visited
-            let realpath = Path::new(&format!("/proc/{}/root", prc.pid))
-                .join(path.strip_prefix("/").unwrap_or(&path));
+            let realpath = roots.pid_path(prc.pid, "root")
+                .join(path.strip_prefix("/").unwrap_or(path));
             let (data, new_meta) = read_values(cache, &realpath);
             if let Some(data) = data {
                 for (desc, value) in data.into_iter() {
-                    if let Ok(key) = key(&pid, cgroup, &name_opt, &desc.json) {
+                    if let Ok(key) = key(&pid, cgroup, &env.appname,
+                                         &desc.json)
+                    {
                         tip.add(key, value);
                     }
                 }
@@ -171,10 +333,70 @@ pub fn read(tip: &mut Tip, cache: &mut ReadCache, processes: &[MinimalProcess],
     }
 }
 
+/// Reads files listed in configs, the same files are read each time
+/// regardless of which processes are running
+pub fn read_static(tip: &mut Tip, cache: &mut ReadCache, configs: &[Config])
+{
+    for cfg in configs {
+        for src in &cfg.files {
+            read_static_file(tip, cache, &src.path, &src.labels);
+        }
+        for src in &cfg.directories {
+            for dir in expand_dirs(&src.path) {
+                ScanDir::files().read(&dir, |iter| {
+                    for (entry, name) in iter {
+                        if name.ends_with(".values") {
+                            let base = entry.path().with_file_name(
+                                &name[..name.len() - ".values".len()]);
+                            read_static_file(tip, cache, &base,
+                                             &src.labels);
+                        }
+                    }
+                }).map_err(|e| debug!("Error reading dir {:?}: {}",
+                                      dir, e)).ok();
+            }
+        }
+    }
+}
+
+fn read_static_file(tip: &mut Tip, cache: &mut ReadCache, path: &PathBuf,
+    labels: &BTreeMap<String, String>)
+{
+    if !add_suffix(path, ".meta").exists() {
+        // Application isn't started yet, or has just been stopped
+        return;
+    }
+    let pairs = labels.iter().map(|(k, v)| (&k[..], &v[..]))
+        .collect::<Vec<_>>();
+    let (data, new_meta) = read_values(cache, path);
+    if let Some(data) = data {
+        for (desc, value) in data.into_iter() {
+            if let Ok(key) = Key::from_json(&desc.json, &pairs) {
+                tip.add(key, value);
+            }
+        }
+    }
+    if let Some(meta) = new_meta {
+        cache.metadata.insert(path.clone(), meta);
+    }
+}
+
+impl EnvironCache {
+    pub fn new() -> EnvironCache {
+        EnvironCache {
+            environ: Arc::new(HashMap::new()),
+            environ_reads: 0,
+        }
+    }
+}
+
 impl ReadCache {
     pub fn new() -> ReadCache {
         ReadCache {
             metadata: HashMap::new(),
+            files_read: 0,
+            metadata_reads: 0,
+            errors: 0,
         }
     }
 }
@@ -203,3 +425,90 @@ mod test_add_suffix {
                    PathBuf::from("/hello/world.values"));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::path::Path;
+    use std::collections::BTreeMap;
+
+    use cantal::Value;
+    use history::Key;
+    use scan::Tip;
+    use super::{Config, Source, ReadCache, read_static, wildcard_match};
+
+    fn write_counter(base: &Path, metric: &str, value: u8) {
+        File::create(base.with_extension("meta")).unwrap()
+            .write_all(format!("counter 1: {{\"metric\": \"{}\"}}\n",
+                               metric).as_bytes())
+            .unwrap();
+        File::create(base.with_extension("values")).unwrap()
+            .write_all(&[value]).unwrap();
+    }
+
+    fn labels(name: &str, value: &str) -> BTreeMap<String, String> {
+        let mut map = BTreeMap::new();
+        map.insert(name.to_string(), value.to_string());
+        return map;
+    }
+
+    fn counter(tip: &Tip, pairs: &[(&str, &str)]) -> Option<u64> {
+        match tip.map.get(&Key::pairs(pairs)) {
+            Some(&Value::Counter(x)) => Some(x),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn wildcards() {
+        let m = |p: &str, n: &str| wildcard_match(
+            &p.chars().collect::<Vec<_>>(), &n.chars().collect::<Vec<_>>());
+        assert!(m("*", "worker1"));
+        assert!(m("worker*", "worker1"));
+        assert!(m("worker?", "worker1"));
+        assert!(m("w*r*1", "worker1"));
+        assert!(!m("worker?", "worker12"));
+        assert!(!m("worker*", "manager"));
+        assert!(!m("", "worker"));
+    }
+
+    #[test]
+    fn static_files() {
+        let root = temp_dir().join("cantal-test-static-values");
+        remove_dir_all(&root).ok();
+        for dir in &["w1", "w2", "other"] {
+            create_dir_all(&root.join(dir)).unwrap();
+        }
+        write_counter(&root.join("single"), "requests", 1);
+        write_counter(&root.join("w1/a"), "w1.requests", 2);
+        write_counter(&root.join("w2/b"), "w2.requests", 3);
+        write_counter(&root.join("other/c"), "other.requests", 4);
+        let configs = vec![Config {
+            files: vec![
+                Source { path: root.join("single"),
+                         labels: labels("appname", "single") },
+                Source { path: root.join("nonexistent"),
+                         labels: labels("appname", "none") },
+            ],
+            directories: vec![
+                Source { path: root.join("w*"),
+                         labels: labels("group", "workers") },
+            ],
+        }];
+        let mut cache = ReadCache::new();
+        let mut tip = Tip::new();
+        read_static(&mut tip, &mut cache, &configs);
+        remove_dir_all(&root).ok();
+        assert_eq!(tip.map.len(), 3);
+        assert_eq!(counter(&tip, &[("appname", "single"),
+                                   ("metric", "requests")]), Some(1));
+        assert_eq!(counter(&tip, &[("group", "workers"),
+                                   ("metric", "w1.requests")]), Some(2));
+        assert_eq!(counter(&tip, &[("group", "workers"),
+                                   ("metric", "w2.requests")]), Some(3));
+        assert_eq!(cache.errors, 0);
+        assert_eq!(cache.metadata_reads, 3);
+    }
+}
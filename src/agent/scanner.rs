@@ -7,49 +7,45 @@ use probor::{Encoder, Encodable};
 
 use super::server;
 use super::stats::Stats;
-use super::scan::Tip;
-use super::scan::machine;
-use super::scan::processes;
-use super::scan::connections;
-use super::scan::values;
+use super::scan::{Tip, Roots};
 use super::scan::time_ms;
-use super::scan::cgroups;
 use super::deps::{Dependencies, LockedDeps};
+use super::configs::Configs;
+use super::collector;
 use cantal::Value;
-use history::VersionInfo;
+use history::{Key, VersionInfo};
 use storage::{Storage, MetricBuffer};
 
 
 const SNAPSHOT_INTERVAL: u64 = 60000;
 
-pub fn scan_loop(deps: Dependencies, interval: u32)
+pub fn scan_loop(deps: Dependencies, interval: u32, threads: usize,
+    configs: Configs, roots: Roots)
 {
     let stats: &RwLock<Stats> = &*deps.copy();
     let storage = deps.get::<Arc<Storage>>().map(|x| &*x);
     let server_msg = deps.get::<mio::Sender<server::Message>>().unwrap();
     let mut last_store = time_ms();
     let mut last_hourly = last_store / 3_600_000;
-    let mut process_cache = processes::ReadCache::new();
-    let mut values_cache = values::ReadCache::new();
+    let mut registry = collector::standard(&deps, interval, threads,
+                                           &configs);
+    let mut state = collector::State::new(roots);
     let mut last_buffer_size = 16 << 10;
     loop {
         let start = time_ms();
         let mut tip = Tip::new();
 
-        let boot_time = machine::read(&mut tip);
-
-        let cgroups = cgroups::read();
-        let processes = processes::read(&mut process_cache, &cgroups);
-        let connections = connections::read();
-        processes::write_tip(&mut tip, &processes, &cgroups);
-        values::read(&mut tip, &mut values_cache, &processes, &cgroups);
+        registry.collect(&mut tip, &mut state, start);
 
         let scan_duration = (time_ms() - start) as u32;
+        tip.add(Key::metric("cantal.scan.duration"),
+            Value::Integer(scan_duration as i64));
 
         if let Ok(ref mut stats) = stats.write() {
             stats.scan_duration = scan_duration;
+            stats.collectors = registry.stats();
             debug!("Got {} values and {} processes in {} ms",
-                tip.map.len(), processes.len(), scan_duration);
+                tip.map.len(), state.processes.len(), scan_duration);
 
             // TODO(tailhook) use drain-style iterator and push to both
             // at once, so we don't need clone (each metric)
@@ -59,9 +55,12 @@ pub fn scan_loop(deps: Dependencies, interval: u32)
                 .filter(|&(_, v)| !matches!(v, &Value::State(_))));
 
             stats.last_scan = start;
-            stats.boot_time = boot_time.or(stats.boot_time);
-            stats.processes = processes;
-            stats.connections = connections;
+            stats.boot_time = state.boot_time.or(stats.boot_time);
+            stats.processes = state.processes.clone();
+            // Connections are kept between runs of their collector
+            if let Some(connections) = state.connections.take() {
+                stats.connections = Some(connections);
+            }
 
             if start - last_store > SNAPSHOT_INTERVAL {
                 last_store = start;
@@ -0,0 +1,493 @@
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+use std::process::{Command, Stdio, Output, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::collections::BTreeMap;
+use std::os::unix::process::{ExitStatusExt, CommandExt};
+
+use libc;
+use quire::validate::{Structure, Scalar, Numeric, Mapping, Sequence};
+use rustc_serialize::json::Json;
+
+use cantal::Value;
+use history::Key;
+use scan::{Tip, time_ms};
+use deps::Dependencies;
+
+
+/// Milliseconds between checks whether the script has exited
+const WAIT_INTERVAL: u64 = 10;
+
+
+#[derive(Debug, RustcDecodable, Clone)]
+pub struct Config {
+    /// Defaults to the name of the config file without `.script.yaml`
+    pub name: Option<String>,
+    pub command: String,
+    pub arguments: Vec<String>,
+    pub interval: u32,
+    pub timeout: u32,
+    pub labels: BTreeMap<String, String>,
+}
+
+pub fn validator<'x>() -> Structure<'x> {
+    Structure::new()
+    .member("name", Scalar::new().optional())
+    .member("command", Scalar::new())
+    .member("arguments", Sequence::new(Scalar::new()))
+    .member("interval",
+        Numeric::new().min(1).max(86400).default(60))
+    .member("timeout",
+        Numeric::new().min(1).max(86400).default(10))
+    .member("labels", Mapping::new(Scalar::new(), Scalar::new()))
+}
+
+/// Last values of every script, in the order of configs
+pub struct Scripts(Vec<Arc<Mutex<Vec<(Key, Value)>>>>);
+
+impl Scripts {
+    pub fn write_tip(&self, tip: &mut Tip) {
+        for script in self.0.iter() {
+            for &(ref key, ref value) in script.lock().unwrap().iter() {
+                tip.add(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Labels printed by the script named `metric` and `script` are renamed to
+/// `exported_metric` and `exported_script`, so metrics of different
+/// scripts and built-in metrics can't be overwritten
+fn key(name: &str, script: &str, labels: &BTreeMap<String, String>,
+    extra: &BTreeMap<String, String>)
+    -> Key
+{
+    let mut pairs = labels.iter()
+        .map(|(k, v)| match &k[..] {
+            "metric" | "script" => (format!("exported_{}", k), v.clone()),
+            _ => (k.clone(), v.clone()),
+        })
+        .collect::<BTreeMap<_, _>>();
+    for (k, v) in extra.iter() {
+        pairs.insert(k.clone(), v.clone());
+    }
+    pairs.insert("script".to_string(), script.to_string());
+    pairs.insert("metric".to_string(), name.to_string());
+    let pairs = pairs.iter().map(|(k, v)| (&k[..], &v[..]))
+        .collect::<Vec<_>>();
+    Key::pairs(&pairs)
+}
+
+fn number(value: &str) -> Option<Value> {
+    if let Ok(x) = value.parse::<i64>() {
+        return Some(Value::Integer(x));
+    }
+    match value.parse::<f64>() {
+        Ok(x) if x.is_finite() => Some(Value::Float(x)),
+        _ => None,
+    }
+}
+
+fn json_number(value: &Json) -> Option<Value> {
+    match *value {
+        Json::I64(x) => Some(Value::Integer(x)),
+        Json::U64(x) if x <= i64::max_value() as u64
+        => Some(Value::Integer(x as i64)),
+        Json::F64(x) if x.is_finite() => Some(Value::Float(x)),
+        _ => None,
+    }
+}
+
+/// Parses lines like `name=value label1=a label2=b`
+fn parse_lines(text: &str, script: &str, extra: &BTreeMap<String, String>)
+    -> Vec<(Key, Value)>
+{
+    let mut result = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.len() == 0 || line.starts_with("#") {
+            continue;
+        }
+        let mut words = line.split_whitespace().map(|x| {
+            let mut pair = x.splitn(2, '=');
+            (pair.next().unwrap(), pair.next())
+        });
+        let (name, value) = match words.next() {
+            Some((name, Some(value))) if name.len() > 0 => {
+                match number(value) {
+                    Some(value) => (name, value),
+                    None => {
+                        debug!("Bad script value {:?}", line);
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                debug!("Bad script line {:?}", line);
+                continue;
+            }
+        };
+        let mut labels = BTreeMap::new();
+        let mut ok = true;
+        for (lname, lvalue) in words {
+            match lvalue {
+                Some(lvalue) if lname.len() > 0 => {
+                    labels.insert(lname.to_string(), lvalue.to_string());
+                }
+                _ => {
+                    ok = false;
+                    break;
+                }
+            }
+        }
+        if !ok {
+            debug!("Bad script labels {:?}", line);
+            continue;
+        }
+        result.push((key(name, script, &labels, extra), value));
+    }
+    return result;
+}
+
+/// Parses either `{"name": value, ...}` or a list of objects like
+/// `{"metric": "name", "value": value, "label1": "a"}`
+fn parse_json(data: &Json, script: &str, extra: &BTreeMap<String, String>)
+    -> Vec<(Key, Value)>
+{
+    let mut result = Vec::new();
+    match *data {
+        Json::Object(ref obj) => {
+            for (name, value) in obj.iter() {
+                match json_number(value) {
+                    Some(value) => {
+                        result.push((key(name, script, &BTreeMap::new(),
+                                         extra), value));
+                    }
+                    None => debug!("Bad script value for {:?}", name),
+                }
+            }
+        }
+        Json::Array(ref items) => {
+            for item in items.iter() {
+                let obj = match item.as_object() {
+                    Some(obj) => obj,
+                    None => continue,
+                };
+                let name = obj.get("metric").and_then(|x| x.as_string());
+                let value = obj.get("value").and_then(json_number);
+                let (name, value) = match (name, value) {
+                    (Some(name), Some(value)) => (name, value),
+                    _ => {
+                        debug!("Bad script item {}", item);
+                        continue;
+                    }
+                };
+                let labels = obj.iter()
+                    .filter(|&(k, _)| k != "metric" && k != "value")
+                    .filter_map(|(k, v)| {
+                        v.as_string().map(|v| (k.clone(), v.to_string()))
+                    })
+                    .collect();
+                result.push((key(name, script, &labels, extra), value));
+            }
+        }
+        _ => debug!("Script output is neither an object nor an array"),
+    }
+    return result;
+}
+
+/// Parses output of the script, it's JSON if it starts with `{` or `[`
+///
+/// `extra` labels override labels printed by the script, and every metric
+/// has the `script` label with the name of the script
+pub fn parse(text: &str, script: &str, extra: &BTreeMap<String, String>)
+    -> Vec<(Key, Value)>
+{
+    let trimmed = text.trim_left();
+    if trimmed.starts_with("{") || trimmed.starts_with("[") {
+        match Json::from_str(trimmed) {
+            Ok(data) => parse_json(&data, script, extra),
+            Err(e) => {
+                debug!("Bad script JSON: {}", e);
+                Vec::new()
+            }
+        }
+    } else {
+        parse_lines(text, script, extra)
+    }
+}
+
+enum Outcome {
+    Finished(Output),
+    Failed(String),
+    TimedOut,
+}
+
+/// Runs the command, killing its process group when timeout expires
+///
+/// The script is run in its own session, so processes it spawns are in
+/// the same process group and are killed too, unless they change group.
+fn execute(cfg: &Config) -> Outcome {
+    let mut child = match Command::new(&cfg.command)
+        .args(&cfg.arguments)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .before_exec(|| {
+            if unsafe { libc::setsid() } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Outcome::Failed(format!("{}", e)),
+    };
+    let pid = child.id() as libc::pid_t;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        tx.send(stdout.read_to_end(&mut buf).map(|_| buf)).ok();
+    });
+    let deadline = time_ms() + cfg.timeout as u64 * 1000;
+    // The script is reaped by `waitpid` below rather than in a thread, so
+    // until it's reaped its pid, which is also the id of the process
+    // group, can't be reused and it's safe to kill the group
+    let mut status = 0;
+    loop {
+        let res = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+        if res == pid {
+            break;
+        } else if res < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::kill(-pid, libc::SIGKILL) };
+            return Outcome::Failed(format!("{}", err));
+        } else if time_ms() >= deadline {
+            unsafe {
+                libc::kill(-pid, libc::SIGKILL);
+                libc::waitpid(pid, &mut status, 0);
+            }
+            return Outcome::TimedOut;
+        }
+        thread::sleep(Duration::from_millis(WAIT_INTERVAL));
+    }
+    // Processes started in background may still keep stdout open. The
+    // group exists while any of them is alive, so its id is still not
+    // reused if we need to kill them
+    let left = deadline.saturating_sub(time_ms());
+    match rx.recv_timeout(Duration::from_millis(left)) {
+        Ok(Ok(stdout)) => Outcome::Finished(Output {
+            status: ExitStatus::from_raw(status),
+            stdout: stdout,
+            stderr: Vec::new(),
+        }),
+        Ok(Err(e)) => Outcome::Failed(format!("{}", e)),
+        Err(RecvTimeoutError::Timeout) => {
+            unsafe { libc::kill(-pid, libc::SIGKILL) };
+            Outcome::TimedOut
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            Outcome::Failed("reading thread crashed".to_string())
+        }
+    }
+}
+
+fn run(cfg: &Config, name: &str, timeouts: &mut u64) -> Vec<(Key, Value)> {
+    let start = time_ms();
+    let outcome = execute(cfg);
+    let duration = time_ms().saturating_sub(start);
+    let mut values = Vec::new();
+    let own = |metric: &str| Key::pairs(&[
+        ("metric", metric),
+        ("script", name),
+    ]);
+    match outcome {
+        Outcome::Finished(output) => {
+            // Same as shell does for the processes killed by signal
+            let code = output.status.code()
+                .or(output.status.signal().map(|x| 128 + x))
+                .unwrap_or(-1);
+            if code != 0 {
+                info!("Script {:?} exited with {}", name, output.status);
+            }
+            values.extend(parse(&String::from_utf8_lossy(&output.stdout),
+                                name, &cfg.labels));
+            values.push((own("script.exit_code"),
+                         Value::Integer(code as i64)));
+        }
+        Outcome::Failed(e) => {
+            warn!("Error running script {:?}: {}", name, e);
+        }
+        Outcome::TimedOut => {
+            warn!("Script {:?} timed out after {} seconds",
+                  name, cfg.timeout);
+            *timeouts += 1;
+        }
+    }
+    values.push((own("script.duration"), Value::Integer(duration as i64)));
+    values.push((own("script.timeouts"), Value::Counter(*timeouts)));
+    return values;
+}
+
+fn script_loop(cfg: Config, script: Arc<Mutex<Vec<(Key, Value)>>>) {
+    let name = cfg.name.clone().unwrap_or_else(|| cfg.command.clone());
+    let interval = cfg.interval as u64 * 1000;
+    let mut timeouts = 0;
+    loop {
+        *script.lock().unwrap() = run(&cfg, &name, &mut timeouts);
+        thread::sleep(Duration::from_millis(
+            interval - time_ms() % interval));
+    }
+}
+
+/// Starts a thread per script and puts `Scripts` into dependencies
+pub fn start(deps: &mut Dependencies, configs: &[Config]) {
+    if configs.len() == 0 {
+        return;
+    }
+    let mut scripts = Vec::new();
+    for cfg in configs {
+        let script = Arc::new(Mutex::new(Vec::new()));
+        scripts.push(script.clone());
+        let cfg = cfg.clone();
+        thread::spawn(move || {
+            script_loop(cfg, script);
+        });
+    }
+    deps.insert(Arc::new(Scripts(scripts)));
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use cantal::Value;
+    use history::Key;
+    use scan::time_ms;
+    use super::{parse, run, Config};
+
+    fn get<'x>(values: &'x [(Key, Value)], key: &Key) -> Option<&'x Value> {
+        values.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v)
+    }
+
+    fn config(script: &str, timeout: u32) -> Config {
+        Config {
+            name: Some("test".to_string()),
+            command: "/bin/sh".to_string(),
+            arguments: vec!["-c".to_string(), script.to_string()],
+            interval: 60,
+            timeout: timeout,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        let mut extra = BTreeMap::new();
+        extra.insert("host".to_string(), "a".to_string());
+        let values = parse("# comment\n\
+                            raid.degraded=1 array=md0\n\
+                            cert.days_left=12.5\n\
+                            broken\n\
+                            bad=1 label\n\
+                            cantal.scan.duration=0 script=x\n",
+                           "raid", &extra);
+        assert_eq!(values.len(), 3);
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("array", "md0"),
+                ("host", "a"),
+                ("metric", "raid.degraded"),
+                ("script", "raid"),
+            ])), Some(&Value::Integer(1))));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("host", "a"),
+                ("metric", "cert.days_left"),
+                ("script", "raid"),
+            ])), Some(&Value::Float(x)) if x == 12.5));
+        // script can't override its own name or built-in metrics
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("exported_script", "x"),
+                ("host", "a"),
+                ("metric", "cantal.scan.duration"),
+                ("script", "raid"),
+            ])), Some(&Value::Integer(0))));
+    }
+
+    #[test]
+    fn json() {
+        let values = parse(r#"{"queue": 7, "name": "x"}"#, "mq",
+                           &BTreeMap::new());
+        assert_eq!(values.len(), 1);
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "queue"),
+                ("script", "mq"),
+            ])), Some(&Value::Integer(7))));
+        let values = parse(r#"[
+            {"metric": "queue", "value": 3, "queue": "mail"},
+            {"metric": "queue", "value": 0.5, "queue": "jobs"},
+            {"metric": "broken"}
+        ]"#, "mq", &BTreeMap::new());
+        assert_eq!(values.len(), 2);
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "queue"),
+                ("queue", "mail"),
+                ("script", "mq"),
+            ])), Some(&Value::Integer(3))));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "queue"),
+                ("queue", "jobs"),
+                ("script", "mq"),
+            ])), Some(&Value::Float(x)) if x == 0.5));
+    }
+
+    #[test]
+    fn exit_code() {
+        let mut timeouts = 0;
+        let values = run(&config("echo items=3; exit 2", 5), "test",
+                         &mut timeouts);
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "items"),
+                ("script", "test"),
+            ])), Some(&Value::Integer(3))));
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "script.exit_code"),
+                ("script", "test"),
+            ])), Some(&Value::Integer(2))));
+        assert_eq!(timeouts, 0);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut timeouts = 0;
+        let values = run(&config("echo items=3; exec sleep 10", 1), "test",
+                         &mut timeouts);
+        assert!(get(&values, &Key::pairs(&[
+                ("metric", "items"),
+                ("script", "test"),
+            ])).is_none());
+        assert!(matches!(get(&values, &Key::pairs(&[
+                ("metric", "script.timeouts"),
+                ("script", "test"),
+            ])), Some(&Value::Counter(1))));
+        assert_eq!(timeouts, 1);
+    }
+
+    #[test]
+    fn background_timeout() {
+        let mut timeouts = 0;
+        let start = time_ms();
+        // the shell exits, but its child keeps stdout open
+        let values = run(&config("sleep 10 & echo items=3", 1), "test",
+                         &mut timeouts);
+        assert!(time_ms() - start < 5000);
+        assert!(get(&values, &Key::pairs(&[
+                ("metric", "items"),
+                ("script", "test"),
+            ])).is_none());
+        assert_eq!(timeouts, 1);
+    }
+}
@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use std::default::Default;
 
 use libc::pid_t;
@@ -6,6 +7,7 @@ use super::scan::time_ms;
 use super::scan;
 use history::History;
 use super::storage::StorageStats;
+use super::collector::CollectorStats;
 
 
 pub struct Stats {
@@ -20,10 +22,11 @@ pub struct Stats {
     pub last_scan: u64,
     pub scan_duration: u32,
     pub boot_time: Option<u64>,
+    pub collectors: Vec<CollectorStats>,
 
     pub storage: StorageStats,
     pub history: History,
-    pub processes: Vec<scan::processes::MinimalProcess>,
+    pub processes: Arc<Vec<scan::processes::MinimalProcess>>,
     pub connections: Option<scan::connections::Connections>,
 }
 
@@ -44,6 +47,7 @@ impl Stats {
             last_scan: 0,
             scan_duration: 0,
             boot_time: None,
+            collectors: Vec::new(),
             storage: Default::default(),
             history: History::new(),
             processes: Default::default(),
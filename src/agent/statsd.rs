@@ -0,0 +1,423 @@
+use std::io;
+use std::thread;
+use std::cmp::min;
+use std::str::from_utf8;
+use std::time::Duration;
+use std::net::{UdpSocket, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+
+use cantal::Value;
+use history::Key;
+use scan::{Tip, time_ms};
+use deps::Dependencies;
+
+
+const MAX_PACKET: usize = 65536;
+/// Samples of new names are dropped when this number of names is reached
+const MAX_NAMES: usize = 10000;
+/// New members of a set are dropped when it has this number of members
+const MAX_SET_MEMBERS: usize = 1000;
+/// Names which were not updated for this long are forgotten (ten minutes)
+const EXPIRE_MS: u64 = 600_000;
+/// Maximum pause after errors of receiving packets
+const MAX_BACKOFF_MS: u64 = 5000;
+
+
+#[derive(Debug, PartialEq)]
+enum Sample<'a> {
+    Counter(&'a str, f64),
+    /// Last value is true if it's a relative change (`+N` or `-N`)
+    Gauge(&'a str, f64, bool),
+    Timer(&'a str, f64),
+    Set(&'a str, &'a str),
+}
+
+/// Always reported as float: statsd counters are fractional when sampled
+/// and may be decremented, which cantal counters can't, and switching the
+/// type on the first decrement would reset the history of the metric
+#[derive(Default)]
+struct Counter {
+    sum: f64,
+    /// Time (ms) of the last update
+    updated: u64,
+}
+
+#[derive(Default)]
+struct Gauge {
+    value: f64,
+    updated: u64,
+}
+
+#[derive(Default)]
+struct Timer {
+    /// Total number of samples since agent started
+    total: u64,
+    updated: u64,
+    /// The fields below are reset on every scan
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+/// Accumulates statsd metrics between scans
+///
+/// Counters and gauges keep their values until they are not updated for
+/// `EXPIRE_MS` (counters are cumulative like in mmap protocol, so
+/// rates are calculated by cantal). Sets and statistics of timers are per
+/// scan interval.
+pub struct Aggregator {
+    counters: HashMap<String, Counter>,
+    gauges: HashMap<String, Gauge>,
+    timers: HashMap<String, Timer>,
+    sets: HashMap<String, HashSet<String>>,
+    /// Number of samples dropped because of `MAX_NAMES` and
+    /// `MAX_SET_MEMBERS` limits
+    pub dropped: u64,
+    /// Number of names forgotten after `EXPIRE_MS` without updates
+    pub expired: u64,
+}
+
+fn parse_line(line: &str) -> Option<Sample> {
+    let mut parts = line.split('|');
+    // split always yields at least one item
+    let head = parts.next().unwrap();
+    let (name, value) = match head.rfind(':') {
+        Some(idx) if idx > 0 => (&head[..idx], &head[idx+1..]),
+        _ => return None,
+    };
+    let kind = match parts.next() {
+        Some(x) => x,
+        None => return None,
+    };
+    let mut rate = 1.0;
+    for item in parts {
+        if item.starts_with("@") {
+            rate = match item[1..].parse() {
+                Ok(x) if x > 0. && x <= 1. => x,
+                _ => return None,
+            };
+        }
+        // tags (`#name:value`) are not supported yet, so ignored
+    }
+    match kind {
+        "c" => value.parse().ok().map(|x: f64| Sample::Counter(name, x/rate)),
+        "g" => {
+            if value.starts_with("+") {
+                value[1..].parse().ok().map(|x| Sample::Gauge(name, x, true))
+            } else {
+                let relative = value.starts_with("-");
+                value.parse().ok().map(|x| Sample::Gauge(name, x, relative))
+            }
+        }
+        "ms" | "h" => value.parse().ok().map(|x| Sample::Timer(name, x)),
+        "s" => Some(Sample::Set(name, value)),
+        _ => None,
+    }
+}
+
+impl Aggregator {
+    pub fn new() -> Aggregator {
+        Aggregator {
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+            timers: HashMap::new(),
+            sets: HashMap::new(),
+            dropped: 0,
+            expired: 0,
+        }
+    }
+    fn num_names(&self) -> usize {
+        self.counters.len() + self.gauges.len() +
+        self.timers.len() + self.sets.len()
+    }
+    fn add(&mut self, sample: Sample, now: u64) {
+        let known = match sample {
+            Sample::Counter(name, _) => self.counters.contains_key(name),
+            Sample::Gauge(name, _, _) => self.gauges.contains_key(name),
+            Sample::Timer(name, _) => self.timers.contains_key(name),
+            Sample::Set(name, _) => self.sets.contains_key(name),
+        };
+        if !known && self.num_names() >= MAX_NAMES {
+            self.dropped += 1;
+            return;
+        }
+        match sample {
+            Sample::Counter(name, x) => {
+                let c = self.counters.entry(name.to_string())
+                    .or_insert_with(Default::default);
+                c.sum += x;
+                c.updated = now;
+            }
+            Sample::Gauge(name, x, relative) => {
+                let g = self.gauges.entry(name.to_string())
+                    .or_insert_with(Default::default);
+                if relative {
+                    g.value += x;
+                } else {
+                    g.value = x;
+                }
+                g.updated = now;
+            }
+            Sample::Timer(name, x) => {
+                let t = self.timers.entry(name.to_string())
+                    .or_insert_with(Default::default);
+                if t.count == 0 || x < t.min { t.min = x; }
+                if t.count == 0 || x > t.max { t.max = x; }
+                t.total += 1;
+                t.count += 1;
+                t.sum += x;
+                t.updated = now;
+            }
+            Sample::Set(name, x) => {
+                let set = self.sets.entry(name.to_string())
+                    .or_insert_with(HashSet::new);
+                if set.contains(x) {
+                    return;
+                }
+                if set.len() >= MAX_SET_MEMBERS {
+                    self.dropped += 1;
+                    return;
+                }
+                set.insert(x.to_string());
+            }
+        }
+    }
+    /// Puts values to the tip and resets per-interval statistics
+    ///
+    /// Names which were not updated for `EXPIRE_MS` before `now` are
+    /// forgotten.
+    pub fn write_tip(&mut self, tip: &mut Tip, now: u64) {
+        self.expired += expire(&mut self.counters, |c| c.updated, now);
+        self.expired += expire(&mut self.gauges, |g| g.updated, now);
+        self.expired += expire(&mut self.timers, |t| t.updated, now);
+        for (name, c) in self.counters.iter() {
+            tip.add(key(name), Value::Float(c.sum));
+        }
+        for (name, g) in self.gauges.iter() {
+            tip.add(key(name), Value::Float(g.value));
+        }
+        for (name, timer) in self.timers.iter_mut() {
+            tip.add(key(&format!("{}.count", name)),
+                Value::Counter(timer.total));
+            if timer.count > 0 {
+                tip.add(key(&format!("{}.min", name)),
+                    Value::Float(timer.min));
+                tip.add(key(&format!("{}.max", name)),
+                    Value::Float(timer.max));
+                tip.add(key(&format!("{}.mean", name)),
+                    Value::Float(timer.sum / timer.count as f64));
+            }
+            timer.count = 0;
+            timer.sum = 0.;
+        }
+        for (name, set) in self.sets.drain() {
+            tip.add(key(&name), Value::Integer(set.len() as i64));
+        }
+    }
+}
+
+/// Removes items which were updated `EXPIRE_MS` or more before `now`
+fn expire<T, F>(map: &mut HashMap<String, T>, updated: F, now: u64) -> u64
+    where F: Fn(&T) -> u64
+{
+    let names = map.iter()
+        .filter(|&(_, item)| now >= updated(item) + EXPIRE_MS)
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    for name in &names {
+        map.remove(name);
+    }
+    return names.len() as u64;
+}
+
+fn key(name: &str) -> Key {
+    Key::pairs(&[
+        ("metric", name),
+        ("source", "statsd"),
+    ])
+}
+
+fn receive_loop(sock: UdpSocket, aggregator: Arc<Mutex<Aggregator>>) {
+    let mut buf = vec![0u8; MAX_PACKET];
+    let mut backoff = 0;
+    loop {
+        let bytes = match sock.recv_from(&mut buf) {
+            Ok((bytes, _)) => bytes,
+            Err(e) => {
+                error!("Error receiving statsd packet: {}", e);
+                // don't spin on persistent errors
+                backoff = min(backoff * 2 + 10, MAX_BACKOFF_MS);
+                thread::sleep(Duration::from_millis(backoff));
+                continue;
+            }
+        };
+        backoff = 0;
+        let data = match from_utf8(&buf[..bytes]) {
+            Ok(data) => data,
+            Err(_) => {
+                debug!("Statsd packet is not utf-8");
+                continue;
+            }
+        };
+        let now = time_ms();
+        let mut aggr = aggregator.lock().unwrap();
+        for line in data.lines() {
+            if line.len() == 0 {
+                continue;
+            }
+            match parse_line(line) {
+                Some(sample) => aggr.add(sample, now),
+                None => debug!("Bad statsd line {:?}", line),
+            }
+        }
+    }
+}
+
+/// Binds the socket and starts a thread that receives metrics
+///
+/// The aggregator is put into dependencies, to be drained by the scanner
+pub fn start(deps: &mut Dependencies, addr: &SocketAddr)
+    -> Result<(), io::Error>
+{
+    let sock = try!(UdpSocket::bind(addr));
+    let aggregator = Arc::new(Mutex::new(Aggregator::new()));
+    deps.insert(aggregator.clone());
+    thread::spawn(move || {
+        receive_loop(sock, aggregator);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use cantal::Value;
+    use scan::Tip;
+    use super::{parse_line, Aggregator, Sample, key};
+    use super::{MAX_NAMES, MAX_SET_MEMBERS, EXPIRE_MS};
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_line("hits:1|c"), Some(Sample::Counter("hits", 1.)));
+        assert_eq!(parse_line("hits:1|c|@0.5"),
+                   Some(Sample::Counter("hits", 2.)));
+        assert_eq!(parse_line("temp:-3|g"),
+                   Some(Sample::Gauge("temp", -3., true)));
+        assert_eq!(parse_line("temp:3.5|g|#host:a"),
+                   Some(Sample::Gauge("temp", 3.5, false)));
+        assert_eq!(parse_line("a.b:12|ms"), Some(Sample::Timer("a.b", 12.)));
+        assert_eq!(parse_line("users:joe|s"),
+                   Some(Sample::Set("users", "joe")));
+        assert_eq!(parse_line("hits|c"), None);
+        assert_eq!(parse_line(":1|c"), None);
+        assert_eq!(parse_line("hits:x|c"), None);
+        assert_eq!(parse_line("hits:1|x"), None);
+        assert_eq!(parse_line("hits:1|c|@2"), None);
+    }
+
+    #[test]
+    fn aggregate() {
+        let mut aggr = Aggregator::new();
+        for line in &["hits:1|c", "hits:2|c", "t:10|ms", "t:20|ms",
+                      "u:a|s", "u:b|s", "u:a|s", "g:5|g", "g:+1|g"]
+        {
+            aggr.add(parse_line(line).unwrap(), 0);
+        }
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 0);
+        assert!(matches!(tip.map.get(&key("hits")),
+                         Some(&Value::Float(x)) if x == 3.));
+        assert!(matches!(tip.map.get(&key("t.count")),
+                         Some(&Value::Counter(2))));
+        assert!(matches!(tip.map.get(&key("t.mean")),
+                         Some(&Value::Float(x)) if x == 15.));
+        assert!(matches!(tip.map.get(&key("u")), Some(&Value::Integer(2))));
+        assert!(matches!(tip.map.get(&key("g")),
+                         Some(&Value::Float(x)) if x == 6.));
+
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 0);
+        assert!(matches!(tip.map.get(&key("hits")),
+                         Some(&Value::Float(x)) if x == 3.));
+        assert!(matches!(tip.map.get(&key("t.count")),
+                         Some(&Value::Counter(2))));
+        assert!(tip.map.get(&key("t.mean")).is_none());
+        assert!(tip.map.get(&key("u")).is_none());
+    }
+
+    #[test]
+    fn fractional_and_negative_counters() {
+        let mut aggr = Aggregator::new();
+        for line in &["hits:1|c|@0.3", "hits:1|c|@0.3", "hits:1|c|@0.3",
+                      "queue:5|c", "queue:-7|c"]
+        {
+            aggr.add(parse_line(line).unwrap(), 0);
+        }
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 0);
+        assert!(matches!(tip.map.get(&key("hits")),
+                         Some(&Value::Float(x)) if (x - 10.).abs() < 1e-9));
+        assert!(matches!(tip.map.get(&key("queue")),
+                         Some(&Value::Float(x)) if x == -2.));
+
+        // The type doesn't change when counter is decremented later
+        aggr.add(parse_line("hits:-1|c").unwrap(), 0);
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 0);
+        assert!(matches!(tip.map.get(&key("hits")),
+                         Some(&Value::Float(x)) if (x - 9.).abs() < 1e-9));
+    }
+
+    #[test]
+    fn expire() {
+        let mut aggr = Aggregator::new();
+        aggr.add(parse_line("old:1|c").unwrap(), 1000);
+        aggr.add(parse_line("t:1|ms").unwrap(), 1000);
+        // Scans may be delayed, so it's time that matters not their number
+        for &now in &[3000, 1000+EXPIRE_MS/2] {
+            aggr.add(parse_line("new:1|g").unwrap(), now);
+            aggr.write_tip(&mut Tip::new(), now);
+        }
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 1000+EXPIRE_MS-1);
+        assert!(tip.map.get(&key("old")).is_some());
+        assert!(tip.map.get(&key("new")).is_some());
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 1000+EXPIRE_MS);
+        assert!(tip.map.get(&key("old")).is_none());
+        assert!(tip.map.get(&key("t.count")).is_none());
+        assert!(tip.map.get(&key("new")).is_some());
+        assert_eq!(aggr.expired, 2);
+    }
+
+    #[test]
+    fn max_names() {
+        let mut aggr = Aggregator::new();
+        for i in 0..MAX_NAMES {
+            aggr.add(Sample::Gauge(&format!("g{}", i), 1., false), 0);
+        }
+        aggr.add(Sample::Counter("extra", 1.), 0);
+        aggr.add(Sample::Gauge("g0", 2., false), 0);
+        assert_eq!(aggr.dropped, 1);
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 0);
+        assert_eq!(tip.map.len(), MAX_NAMES);
+        assert!(matches!(tip.map.get(&key("g0")),
+                         Some(&Value::Float(x)) if x == 2.));
+    }
+
+    #[test]
+    fn max_set_members() {
+        let mut aggr = Aggregator::new();
+        for i in 0..MAX_SET_MEMBERS+1 {
+            aggr.add(Sample::Set("users", &format!("u{}", i)), 0);
+        }
+        aggr.add(Sample::Set("users", "u0"), 0);
+        assert_eq!(aggr.dropped, 1);
+        let mut tip = Tip::new();
+        aggr.write_tip(&mut tip, 0);
+        assert!(matches!(tip.map.get(&key("users")),
+            Some(&Value::Integer(x)) if x == MAX_SET_MEMBERS as i64));
+    }
+}